@@ -0,0 +1,504 @@
+use std::{collections::BTreeMap, fmt, path::{Path, PathBuf}, fs, process::Command};
+
+use regex::{NoExpand, Regex};
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::{command::{command_output::command_output_logged, interrupt, log::CommandLog}, report, roller::{gitlog::{short_hash, LogContext}, stash::{install_prefix, parse_commit_context_json, Side}}};
+
+use super::toolchain::Toolchain;
+
+/// What `diff-codegen` has rustc emit.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Emit {
+    Mir,
+    LlvmIr,
+    Asm
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::Mir => "mir",
+            Emit::LlvmIr => "ll",
+            Emit::Asm => "s",
+        }
+    }
+}
+
+pub struct CodegenOptions {
+    pub emits: Vec<Emit>,
+    pub opt_level: String,
+    // Of a single source file, which is compiled with rustc directly.
+    pub crate_type: String,
+    pub edition: String,
+    // Passed to rustc after the flags `diff-codegen` sets.
+    pub rustc_args: Vec<String>,
+    // Only diff the context with this title.
+    pub title: Option<String>
+}
+
+/// One function that differs between the two sides.
+#[derive(Serialize, Debug)]
+pub struct FunctionChange {
+    pub name: String,
+    // Missing on the side that does not have the function.
+    pub old_instructions: Option<usize>,
+    pub cur_instructions: Option<usize>,
+    pub diff: PathBuf
+}
+
+#[derive(Serialize, Debug)]
+pub struct EmitReport {
+    pub emit: Emit,
+    pub old_functions: usize,
+    pub cur_functions: usize,
+    pub unchanged: usize,
+    pub changes: Vec<FunctionChange>
+}
+
+#[derive(Serialize, Debug)]
+pub struct CodegenReport {
+    pub title: String,
+    pub hash_old: String,
+    pub hash_cur: String,
+    pub emits: Vec<EmitReport>
+}
+
+/// Compiles `input`, a source file or a crate directory, with the toolchains
+/// `roll_back` installed for both sides of each context, emitting MIR, LLVM
+/// IR and assembly. The output is normalized so that hashes and paths that
+/// differ between the toolchains don't show up as changes, split into
+/// functions, and every function that differs gets a unified diff under
+/// `<out_dir>/<title>/diff/<emit>`. The changes are summarized in
+/// `<out_dir>/codegen.json`. Returns whether every context was diffed.
+pub fn diff_codegen(commit_context_json: &Path, install_root: &Path, input: &Path, out_dir: &Path, options: &CodegenOptions) -> bool {
+    let contexts = match parse_commit_context_json(commit_context_json) {
+        Ok(contexts) => contexts,
+        Err(err) => {
+            eprintln!("Fail to parse commit_context_json file {:?}\n{}", commit_context_json, err);
+            return false;
+        }
+    };
+    let input = match input.canonicalize() {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("Fail to find input {:?}\n{}", input, err);
+            return false;
+        }
+    };
+
+    let report_path = out_dir.join("codegen.json");
+    let mut reports = Vec::new();
+    let mut all_ok = true;
+
+    let contexts = contexts.iter()
+        .filter(|context| options.title.as_ref().is_none_or(|title| &context.title == title));
+    for context in contexts {
+        if interrupt::is_interrupted() {
+            return false;
+        }
+        match diff_context(context, install_root, &input, out_dir, options) {
+            Ok(report) => {
+                print_report(&report);
+                reports.push(report);
+            },
+            Err(err) => {
+                eprintln!("Fail to diff codegen of {:?}\n{}", context.title, err);
+                all_ok = false;
+            }
+        }
+    }
+
+    let written = fs::create_dir_all(out_dir).map_err(anyhow::Error::from).and_then(|_| {
+        report::write_json(&report_path, &reports)
+    });
+    match written {
+        Ok(()) => println!("succesfully write codegen diffs of {} contexts to {:?}", reports.len(), report_path),
+        Err(err) => {
+            eprintln!("Fail to write codegen report to {:?}\n{}", report_path, err);
+            return false;
+        }
+    }
+    all_ok
+}
+
+fn diff_context(context: &LogContext, install_root: &Path, input: &Path, out_dir: &Path, options: &CodegenOptions) -> anyhow::Result<CodegenReport> {
+    let context_dir = out_dir.join(context.title.replace(' ', "_"));
+    let [old, cur] = [Side::Old, Side::Cur].map(|side| -> anyhow::Result<BTreeMap<&str, String>> {
+        let toolchain = Toolchain::find(install_root, context, side)?;
+        let dir = context_dir.join(format!("{}_{}", short_hash(side.hash(context)), side.name()));
+        let outputs = emit(&toolchain, input, &dir, options)?;
+        println!("succesfully emit {} of {:?} with {} {}", options.emits.len(), context.title, short_hash(side.hash(context)), side.name());
+
+        let prefix = install_prefix(install_root, context, side);
+        let paths = [(dir.as_path(), "<out>"), (input, "<input>"), (prefix.as_path(), "<prefix>")];
+        Ok(outputs.into_iter().map(|(emit, text)| (emit.extension(), normalize(&text, &paths))).collect())
+    });
+    let (old, cur) = (old?, cur?);
+
+    let mut emits = Vec::new();
+    for emit in &options.emits {
+        let old_functions = functions(*emit, &old[emit.extension()]);
+        let cur_functions = functions(*emit, &cur[emit.extension()]);
+        let diff_dir = context_dir.join("diff").join(emit.extension());
+        if diff_dir.exists() {
+            fs::remove_dir_all(&diff_dir)?;
+        }
+
+        let mut names: Vec<&String> = old_functions.keys().chain(cur_functions.keys()).collect();
+        names.sort();
+        names.dedup();
+        let mut unchanged = 0;
+        let mut changes = Vec::new();
+        for name in names {
+            let (old_function, cur_function) = (old_functions.get(name), cur_functions.get(name));
+            let old_text = old_function.map(|function| function.text.as_str()).unwrap_or_default();
+            let cur_text = cur_function.map(|function| function.text.as_str()).unwrap_or_default();
+            if old_text == cur_text {
+                unchanged += 1;
+                continue;
+            }
+
+            fs::create_dir_all(&diff_dir)?;
+            let diff = diff_dir.join(format!("{:04}_{}.diff", changes.len(), file_name(name)));
+            let unified = TextDiff::from_lines(old_text, cur_text)
+                .unified_diff()
+                .header(&format!("old/{}", name), &format!("cur/{}", name))
+                .to_string();
+            fs::write(&diff, unified)?;
+            changes.push(FunctionChange {
+                name: name.clone(),
+                old_instructions: old_function.map(|function| function.instructions),
+                cur_instructions: cur_function.map(|function| function.instructions),
+                diff
+            });
+        }
+        emits.push(EmitReport {
+            emit: *emit,
+            old_functions: old_functions.len(),
+            cur_functions: cur_functions.len(),
+            unchanged,
+            changes
+        });
+    }
+
+    Ok(CodegenReport {
+        title: context.title.clone(),
+        hash_old: context.hash_old.clone(),
+        hash_cur: context.hash_cur.clone(),
+        emits
+    })
+}
+
+/// Compiles `input` into the emptied `dir` and reads what rustc emitted. A
+/// crate is built with `cargo rustc`, for its library when it has one.
+fn emit(toolchain: &Toolchain, input: &Path, dir: &Path, options: &CodegenOptions) -> anyhow::Result<Vec<(Emit, String)>> {
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    fs::create_dir_all(dir)?;
+
+    let emits = options.emits.iter().map(ToString::to_string).collect::<Vec<_>>().join(",");
+    let rustc_args = [
+        format!("--emit={}", emits),
+        format!("-Copt-level={}", options.opt_level),
+        // One file per kind, with functions in a stable order.
+        String::from("-Ccodegen-units=1"),
+        String::from("-Cdebuginfo=0"),
+    ];
+
+    let (mut cmd, emitted_dir) = if input.is_dir() {
+        let target_dir = dir.join("target");
+        let mut cmd = Command::new(&toolchain.cargo);
+        cmd.current_dir(input).arg("rustc");
+        if input.join("src").join("lib.rs").is_file() {
+            cmd.arg("--lib");
+        }
+        cmd.arg("--").args(&rustc_args).args(&options.rustc_args)
+            .env("RUSTC", &toolchain.rustc)
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .env("CARGO_INCREMENTAL", "0");
+        (cmd, target_dir.join("debug").join("deps"))
+    } else {
+        let mut cmd = Command::new(&toolchain.rustc);
+        cmd.current_dir(dir)
+            .arg(input)
+            .arg(format!("--crate-type={}", options.crate_type))
+            .arg(format!("--edition={}", options.edition))
+            .arg("--out-dir").arg(dir)
+            .args(&rustc_args).args(&options.rustc_args);
+        (cmd, dir.to_path_buf())
+    };
+    let mut log = CommandLog::create(&dir.join("compile.log"), 20)?;
+    command_output_logged(&mut cmd, None, &mut log)?;
+
+    let mut outputs = Vec::new();
+    for emit in &options.emits {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&emitted_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == emit.extension()) {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(anyhow::anyhow!("rustc emitted no .{} file into {:?}", emit.extension(), emitted_dir));
+        }
+        paths.sort();
+        let mut text = String::new();
+        for path in paths {
+            text += &fs::read_to_string(path)?;
+        }
+        outputs.push((*emit, text));
+    }
+    Ok(outputs)
+}
+
+/// Replaces `paths` by their placeholders, and hashes that depend on the
+/// toolchain, such as symbol hashes, crate disambiguators and the commit of
+/// the rustc source, by `<hash>`.
+fn normalize(text: &str, paths: &[(&Path, &str)]) -> String {
+    let mut text = String::from(text);
+    let mut paths = paths.to_vec();
+    // Longest first, as the out dir may be under the input dir.
+    paths.sort_by_key(|(path, _)| std::cmp::Reverse(path.as_os_str().len()));
+    for (path, placeholder) in paths {
+        text = text.replace(&*path.to_string_lossy(), placeholder);
+    }
+
+    let hashes = [
+        // Legacy mangling, `_ZN5hello3foo17h0123456789abcdefE`, and demangled.
+        (r"17h[0-9a-f]{16}E", "17h<hash>E"),
+        (r"::h[0-9a-f]{16}\b", "::h<hash>"),
+        // Demangled v0 crate disambiguators.
+        (r"\[[0-9a-f]{16}\]", "[<hash>]"),
+        (r"\b(alloc|anon)([._])[0-9a-f]{32}\b", "${1}${2}<hash>"),
+        (r"/rustc/[0-9a-f]{40}", "/rustc/<hash>"),
+        // Metadata suffixes of file names, `hello-0123456789abcdef.ll`.
+        (r"-[0-9a-f]{16}\b", "-<hash>"),
+    ];
+    // Crate disambiguators of v0 mangling, `_RNvCs1a2b3c_5hello3foo`.
+    let disambiguator = Regex::new(r"Cs[0-9A-Za-z]{1,13}_").unwrap();
+    text = Regex::new(r"\b_R[0-9A-Za-z_]+").unwrap()
+        .replace_all(&text, |symbol: &regex::Captures| disambiguator.replace_all(&symbol[0], NoExpand("Cs<hash>_")).into_owned())
+        .into_owned();
+    for (pattern, replacement) in hashes {
+        let regex = Regex::new(pattern).unwrap();
+        text = if replacement.contains('$') {
+            regex.replace_all(&text, replacement).into_owned()
+        } else {
+            regex.replace_all(&text, NoExpand(replacement)).into_owned()
+        };
+    }
+    text
+}
+
+/// The text of one function in an emitted file, with its instructions
+/// counted: statements and terminators in MIR, instructions in LLVM IR and
+/// assembly.
+#[derive(Debug)]
+struct Function {
+    text: String,
+    instructions: usize
+}
+
+/// Splits `text` into functions by name. Items of MIR other than functions,
+/// such as constants and promoted values, count as functions too, while the
+/// data labels of assembly don't.
+fn functions(emit: Emit, text: &str) -> BTreeMap<String, Function> {
+    let asm_functions: Vec<&str> = text.lines()
+        .filter_map(|line| line.strip_prefix("\t.type\t")?.strip_suffix(",@function"))
+        .collect();
+
+    let mut functions = BTreeMap::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let Some(name) = function_name(emit, line, &asm_functions) else {
+            continue;
+        };
+
+        let mut function = Function { text: format!("{}\n", line), instructions: 0 };
+        for line in lines.by_ref() {
+            if is_function_end(emit, &name, line) {
+                function.text += line;
+                function.text += "\n";
+                break;
+            }
+            if is_instruction(emit, line) {
+                function.instructions += 1;
+            }
+            function.text += line;
+            function.text += "\n";
+        }
+
+        function.text = renumber(emit, &function.text);
+        let mut key = name.clone();
+        let mut n = 1;
+        while functions.contains_key(&key) {
+            n += 1;
+            key = format!("{}#{}", name, n);
+        }
+        functions.insert(key, function);
+    }
+    functions
+}
+
+/// Renumbers what is numbered across the whole file by order of first
+/// appearance in `text`, the text of a single function, so that a function
+/// added before it leaves it unchanged: the basic block, constant pool and
+/// temporary labels of assembly, and the attribute groups and metadata of
+/// LLVM IR. MIR numbers everything per function already.
+fn renumber(emit: Emit, text: &str) -> String {
+    let pattern = match emit {
+        Emit::Mir => return String::from(text),
+        // `#0` and `!12`, but not `c"#1"` or `!"name"`.
+        Emit::LlvmIr => r"(^|[\s,({])([#!])(\d+)\b",
+        // `.LBB3_1`, `.LCPI3_0`, `.Lfunc_begin3`, `.Lfunc_end3` and `.Ltmp12`.
+        Emit::Asm => r"()(\.L(?:BB|CPI|func_begin|func_end|tmp))(\d+(?:_\d+)?)\b",
+    };
+    let mut numbers: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    Regex::new(pattern).unwrap()
+        .replace_all(text, |caps: &regex::Captures| {
+            let kind = &caps[2];
+            let number = *numbers.entry((String::from(kind), String::from(&caps[3]))).or_insert_with(|| {
+                let count = counts.entry(String::from(kind)).or_default();
+                *count += 1;
+                *count - 1
+            });
+            format!("{}{}<{}>", &caps[1], kind, number)
+        })
+        .into_owned()
+}
+
+fn function_name(emit: Emit, line: &str, asm_functions: &[&str]) -> Option<String> {
+    let name = match emit {
+        Emit::Mir if !line.starts_with([' ', '\t', '/', '}']) && line.ends_with('{') => match line.strip_prefix("fn ") {
+            Some(rest) => rest.split('(').next().unwrap_or(rest),
+            None => line.split(": ").next().unwrap_or(line),
+        },
+        Emit::LlvmIr if line.starts_with("define ") => {
+            let name = line.split_once('@').map_or(line, |(_, rest)| rest.split('(').next().unwrap_or(rest));
+            name.trim_matches('"')
+        },
+        Emit::Asm => line.strip_suffix(':').filter(|label| asm_functions.contains(label))?,
+        _ => return None,
+    };
+    Some(String::from(name))
+}
+
+fn is_function_end(emit: Emit, name: &str, line: &str) -> bool {
+    match emit {
+        Emit::Mir | Emit::LlvmIr => line == "}",
+        Emit::Asm => line.starts_with(".Lfunc_end")
+            || line.strip_prefix("\t.size\t").and_then(|rest| rest.strip_prefix(name)).is_some_and(|rest| rest.starts_with(',')),
+    }
+}
+
+fn is_instruction(emit: Emit, line: &str) -> bool {
+    let line = line.trim();
+    match emit {
+        Emit::Mir => line.ends_with(';') && !line.starts_with("let ") && !line.starts_with("debug ") && !line.starts_with("//"),
+        Emit::LlvmIr => {
+            let code = line.split(';').next().unwrap_or_default().trim();
+            !code.is_empty() && !code.ends_with(':')
+        },
+        Emit::Asm => !line.is_empty() && !line.starts_with(['.', '#']) && !line.ends_with(':'),
+    }
+}
+
+/// `name` reduced to characters that are safe in a file name.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .take(100)
+        .collect()
+}
+
+fn print_report(report: &CodegenReport) {
+    println!("{} {} -> {}", report.title, short_hash(&report.hash_old), short_hash(&report.hash_cur));
+    for emit in &report.emits {
+        let added = emit.changes.iter().filter(|change| change.old_instructions.is_none()).count();
+        let removed = emit.changes.iter().filter(|change| change.cur_instructions.is_none()).count();
+        println!(
+            "  {}: {} -> {} functions, {} changed, {} added, {} removed",
+            emit.emit, emit.old_functions, emit.cur_functions, emit.changes.len() - added - removed, added, removed
+        );
+
+        let mut resized: Vec<(&FunctionChange, i64)> = emit.changes.iter()
+            .filter_map(|change| {
+                let (old, cur) = (change.old_instructions?, change.cur_instructions?);
+                (old != cur).then_some((change, cur as i64 - old as i64))
+            })
+            .collect();
+        resized.sort_by_key(|(_, delta)| std::cmp::Reverse(delta.abs()));
+        for (change, delta) in resized.iter().take(20) {
+            println!(
+                "    {:>6} -> {:>6} ({:+}) {}",
+                change.old_instructions.unwrap_or_default(), change.cur_instructions.unwrap_or_default(), delta, change.name
+            );
+        }
+        if resized.len() > 20 {
+            println!("    ... and {} more, see {:?}", resized.len() - 20, emit.changes[0].diff.parent().unwrap_or(Path::new(".")));
+        }
+    }
+}
+
+impl fmt::Display for Emit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Emit::Mir => "mir",
+            Emit::LlvmIr => "llvm-ir",
+            Emit::Asm => "asm",
+        };
+        f.write_str(s)
+    }
+}
+
+#[test]
+fn test_functions() {
+    let asm = "\t.text\n\t.section\t.text._ZN5hello3add17h0123456789abcdefE,\"ax\",@progbits\n\
+        \t.type\t_ZN5hello3add17h0123456789abcdefE,@function\n\
+        _ZN5hello3add17h0123456789abcdefE:\n\t.cfi_startproc\n\tleal\t(%rdi,%rsi), %eax\n\tretq\n\
+        .Lfunc_end0:\n\t.size\t_ZN5hello3add17h0123456789abcdefE, .Lfunc_end0-_ZN5hello3add17h0123456789abcdefE\n";
+    let asm = normalize(asm, &[]);
+    let split = functions(Emit::Asm, &asm);
+    assert_eq!(split.keys().collect::<Vec<_>>(), ["_ZN5hello3add17h<hash>E"]);
+    assert_eq!(split["_ZN5hello3add17h<hash>E"].instructions, 2);
+
+    let ir = "define noundef i32 @_ZN5hello3add17h0123456789abcdefE(i32 %a, i32 %b) unnamed_addr #0 {\n\
+        start:\n  %_0 = add i32 %b, %a\n  ret i32 %_0\n}\n\ndeclare void @abort()\n";
+    let split = functions(Emit::LlvmIr, &normalize(ir, &[]));
+    assert_eq!(split["_ZN5hello3add17h<hash>E"].instructions, 2);
+
+    let mir = "// WARNING: This output format is intended for human consumers only\n\
+        fn add(_1: i32, _2: i32) -> i32 {\n    debug a => _1;\n    let mut _0: i32;\n\n    bb0: {\n        _0 = Add(copy _1, copy _2);\n        return;\n    }\n}\n\n\
+        const X: i32 = {\n    let mut _0: i32;\n    bb0: {\n        _0 = const 1_i32;\n        return;\n    }\n}\n";
+    let split = functions(Emit::Mir, mir);
+    assert_eq!(split.keys().collect::<Vec<_>>(), ["add", "const X"]);
+    assert_eq!(split["add"].instructions, 2);
+
+    assert_eq!(normalize("call _RNvCsbs98R3OSHEp_3lib3sum", &[]), "call _RNvCs<hash>_3lib3sum");
+    let paths = [(Path::new("/tmp/out"), "<out>"), (Path::new("/tmp/out/x"), "<x>")];
+    assert_eq!(normalize("/tmp/out/x/a-0123456789abcdef.ll /tmp/out/b", &paths), "<x>/a-<hash>.ll <out>/b");
+}
+
+#[test]
+fn test_renumber_inserted_function() {
+    let old_asm = "\t.type\tb,@function\nb:\n\tmovsd\t.LCPI0_0(%rip), %xmm0\n\tjmp\t.LBB0_2\n.LBB0_1:\n\tretq\n.LBB0_2:\n\tjmp\t.LBB0_1\n.Lfunc_end0:\n";
+    let cur_asm = "\t.type\ta,@function\na:\n\tjmp\t.LBB0_1\n.LBB0_1:\n\tretq\n.Lfunc_end0:\n\
+        \t.type\tb,@function\nb:\n\tmovsd\t.LCPI1_0(%rip), %xmm0\n\tjmp\t.LBB1_2\n.LBB1_1:\n\tretq\n.LBB1_2:\n\tjmp\t.LBB1_1\n.Lfunc_end1:\n";
+    let (old, cur) = (functions(Emit::Asm, old_asm), functions(Emit::Asm, cur_asm));
+    assert_eq!(old["b"].text, cur["b"].text);
+    assert!(cur["b"].text.contains("\tjmp\t.LBB<0>\n.LBB<1>:\n"), "{}", cur["b"].text);
+    assert_ne!(cur["a"].text, cur["b"].text);
+
+    let old_ir = "define void @b() unnamed_addr #0 {\nstart:\n  call void @x(), !dbg !5\n  ret void, !dbg !6\n}\n";
+    let cur_ir = "define void @a() unnamed_addr #0 {\nstart:\n  ret void, !dbg !5\n}\n\n\
+        define void @b() unnamed_addr #1 {\nstart:\n  call void @x(), !dbg !7\n  ret void, !dbg !8\n}\n";
+    let (old, cur) = (functions(Emit::LlvmIr, old_ir), functions(Emit::LlvmIr, cur_ir));
+    assert_eq!(old["b"].text, cur["b"].text);
+    assert!(cur["b"].text.contains("#<0> {") && cur["b"].text.contains("!dbg !<1>"), "{}", cur["b"].text);
+}
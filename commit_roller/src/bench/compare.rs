@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, fmt, path::Path};
+
+use serde::Serialize;
+
+use crate::{report, roller::stash::Side};
+
+use super::{corpus::Mode, results::{BenchResults, Measurement}, stats};
+
+/// What `compare` compares.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Wall,
+    User,
+    Sys,
+    MaxRss,
+    // Counted by `bench --perf-counters`.
+    Instructions,
+    Cycles,
+    BranchMisses,
+    TaskClock
+}
+
+impl Metric {
+    // `None` for counters missing from the measurement.
+    fn value(self, measurement: &Measurement) -> Option<f64> {
+        match self {
+            Metric::Wall => Some(measurement.wall_secs),
+            Metric::User => Some(measurement.user_secs),
+            Metric::Sys => Some(measurement.sys_secs),
+            Metric::MaxRss => Some(measurement.max_rss_kb as f64),
+            Metric::Instructions => measurement.instructions.map(|count| count as f64),
+            Metric::Cycles => measurement.cycles.map(|count| count as f64),
+            Metric::BranchMisses => measurement.branch_misses.map(|count| count as f64),
+            Metric::TaskClock => measurement.task_clock_secs,
+        }
+    }
+}
+
+/// Whether `_cur` is better, worse or not distinguishable from `_old`. Lower
+/// is better for every metric.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Improvement,
+    Regression,
+    Noise,
+    // Fewer than two runs on a side, or an `_old` mean of zero that the
+    // change can't be relative to.
+    Unknown
+}
+
+#[derive(Serialize, Debug)]
+pub struct SideStats {
+    pub runs: usize,
+    pub mean: f64,
+    pub median: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stddev: Option<f64>
+}
+
+/// `_cur` against `_old` for one crate and mode of a context.
+#[derive(Serialize, Debug)]
+pub struct Comparison {
+    pub title: String,
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub mode: Mode,
+    pub metric: Metric,
+    pub old: SideStats,
+    pub cur: SideStats,
+    // Of the mean, in percent of `_old`. Missing when `_old` is zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_pct: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ci_pct: Option<(f64, f64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p_value: Option<f64>,
+    pub verdict: Verdict
+}
+
+pub struct CompareOptions {
+    pub metric: Metric,
+    // Significance level of the t-test; the interval is `1 - alpha`.
+    pub alpha: f64,
+    // Significant changes smaller than this, in percent, are still noise.
+    pub threshold_pct: f64,
+    pub title: Option<String>
+}
+
+/// Compares the measurements of the two sides of every context in
+/// `results`, printing a table and writing the comparisons to `out`.
+pub fn compare(results: &Path, out: &Path, options: &CompareOptions) -> bool {
+    let results = match BenchResults::load(results) {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("Fail to read results {:?}\n{}", results, err);
+            return false;
+        }
+    };
+
+    let comparisons = comparisons(&results, options);
+    print_table(&comparisons);
+
+    match report::write_json(out, &comparisons) {
+        Ok(()) => println!("succesfully write {} comparisons to {:?}", comparisons.len(), out),
+        Err(err) => {
+            eprintln!("Fail to write comparisons to {:?}\n{}", out, err);
+            return false;
+        }
+    }
+    true
+}
+
+fn comparisons(results: &BenchResults, options: &CompareOptions) -> Vec<Comparison> {
+    // Values of each side, by context, crate and mode in the order measured.
+    let mut groups: BTreeMap<(usize, &str, &str, Mode), [Vec<f64>; 2]> = BTreeMap::new();
+    let mut order = Vec::new();
+    for measurement in results.measurements.iter().filter(|m| m.success) {
+        if options.title.as_ref().is_some_and(|title| &measurement.title != title) {
+            continue;
+        }
+        let Some(value) = options.metric.value(measurement) else {
+            continue;
+        };
+        let position = order.iter().position(|title| *title == measurement.title).unwrap_or_else(|| {
+            order.push(measurement.title.clone());
+            order.len() - 1
+        });
+        let side = if measurement.side == Side::Old { 0 } else { 1 };
+        groups.entry((position, &measurement.title, &measurement.krate, measurement.mode))
+            .or_default()[side]
+            .push(value);
+    }
+
+    groups.into_iter()
+        .filter(|(_, [old, cur])| !old.is_empty() && !cur.is_empty())
+        .map(|((_, title, krate, mode), [old, cur])| compare_sides(title, krate, mode, &old, &cur, options))
+        .collect()
+}
+
+fn compare_sides(title: &str, krate: &str, mode: Mode, old: &[f64], cur: &[f64], options: &CompareOptions) -> Comparison {
+    let side_stats = |xs: &[f64]| SideStats {
+        runs: xs.len(),
+        mean: stats::mean(xs),
+        median: stats::median(xs),
+        stddev: stats::stddev(xs)
+    };
+    let (old_stats, cur_stats) = (side_stats(old), side_stats(cur));
+    let base = old_stats.mean;
+    let pct = |x: f64| (base != 0.0).then(|| 100.0 * x / base);
+    let change_pct = pct(cur_stats.mean - old_stats.mean);
+
+    let welch = stats::welch(old, cur, 1.0 - options.alpha);
+    let verdict = match (welch, change_pct) {
+        (None, _) | (_, None) => Verdict::Unknown,
+        (Some(welch), Some(change_pct)) if welch.p_value >= options.alpha || change_pct.abs() < options.threshold_pct => Verdict::Noise,
+        (_, Some(change_pct)) if change_pct < 0.0 => Verdict::Improvement,
+        _ => Verdict::Regression,
+    };
+
+    Comparison {
+        title: String::from(title),
+        krate: String::from(krate),
+        mode,
+        metric: options.metric,
+        old: old_stats,
+        cur: cur_stats,
+        change_pct,
+        ci_pct: welch.and_then(|welch| Some((pct(welch.low)?, pct(welch.high)?))),
+        p_value: welch.map(|welch| welch.p_value),
+        verdict
+    }
+}
+
+fn print_table(comparisons: &[Comparison]) {
+    let header = ["title", "crate", "mode", "old mean", "cur mean", "old sd", "cur sd", "change", "ci", "p", "verdict"];
+    let number = |x: f64| format!("{:.3}", x);
+    let rows: Vec<[String; 11]> = comparisons.iter()
+        .map(|c| [
+            c.title.clone(),
+            c.krate.clone(),
+            c.mode.to_string(),
+            number(c.old.mean),
+            number(c.cur.mean),
+            c.old.stddev.map(number).unwrap_or_default(),
+            c.cur.stddev.map(number).unwrap_or_default(),
+            c.change_pct.map(|pct| format!("{:+.2}%", pct)).unwrap_or_default(),
+            c.ci_pct.map(|(low, high)| format!("[{:+.2}%, {:+.2}%]", low, high)).unwrap_or_default(),
+            c.p_value.map(|p| format!("{:.4}", p)).unwrap_or_default(),
+            c.verdict.to_string(),
+        ])
+        .collect();
+
+    report::print_table(header, &rows);
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Verdict::Improvement => "improvement",
+            Verdict::Regression => "regression",
+            Verdict::Noise => "noise",
+            Verdict::Unknown => "unknown",
+        };
+        f.write_str(s)
+    }
+}
+
+#[test]
+fn test_compare_sides() {
+    let options = CompareOptions { metric: Metric::Wall, alpha: 0.05, threshold_pct: 1.0, title: None };
+    let old = [10.0, 10.2, 9.9, 10.1, 10.0];
+    let verdict = |cur: &[f64]| compare_sides("t", "hello", Mode::Check, &old, cur, &options).verdict;
+    assert_eq!(verdict(&[11.0, 11.1, 10.9, 11.2, 11.0]), Verdict::Regression);
+    assert_eq!(verdict(&[9.0, 9.1, 8.9, 9.2, 9.0]), Verdict::Improvement);
+    assert_eq!(verdict(&[10.1, 9.9, 10.0, 10.2, 10.0]), Verdict::Noise);
+    assert_eq!(verdict(&[11.0]), Verdict::Unknown);
+
+    // Nothing to be relative to, e.g. a counter that stayed at zero.
+    let zero = compare_sides("t", "hello", Mode::Check, &[0.0, 0.0, 0.0], &[0.0, 1.0, 2.0], &options);
+    assert_eq!((zero.change_pct, zero.ci_pct, zero.verdict), (None, None, Verdict::Unknown));
+    assert!(!serde_json::to_string(&zero).unwrap().contains("null"));
+}
@@ -0,0 +1,70 @@
+use std::{collections::BTreeMap, fmt, path::{Path, PathBuf}, fs::read_to_string};
+
+use serde::{Serialize, Deserialize};
+
+/// How a crate of the corpus is compiled.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    Check,
+    Debug,
+    Release
+}
+
+impl Mode {
+    pub fn cargo_args(self) -> &'static [&'static str] {
+        match self {
+            Mode::Check => &["check"],
+            Mode::Debug => &["build"],
+            Mode::Release => &["build", "--release"],
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Mode::Check => "check",
+            Mode::Debug => "debug",
+            Mode::Release => "release",
+        };
+        f.write_str(s)
+    }
+}
+
+/// One local crate compiled by `bench`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CorpusCrate {
+    pub name: String,
+    // Relative to the corpus file.
+    pub path: PathBuf,
+    // Passed to cargo after the mode, e.g. `["--features", "std"]`.
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>
+}
+
+/// The crates listed in a corpus TOML file.
+#[derive(Deserialize, Debug)]
+pub struct Corpus {
+    pub crates: Vec<CorpusCrate>
+}
+
+impl Corpus {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = read_to_string(path)
+            .map_err(|err| anyhow::anyhow!("Fail to read corpus {:?}\n{}", path, err))?;
+        let mut corpus: Corpus = toml::from_str(&text)
+            .map_err(|err| anyhow::anyhow!("Fail to parse corpus {:?}\n{}", path, err))?;
+        if corpus.crates.is_empty() {
+            return Err(anyhow::anyhow!("corpus {:?} has no crates", path));
+        }
+
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        for krate in &mut corpus.crates {
+            krate.path = base.join(&krate.path);
+        }
+        Ok(corpus)
+    }
+}
@@ -0,0 +1,9 @@
+pub mod codegen;
+pub mod compare;
+pub mod corpus;
+pub mod profile;
+pub mod results;
+pub mod runner;
+pub mod size;
+pub mod stats;
+pub mod toolchain;
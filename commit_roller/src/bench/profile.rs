@@ -0,0 +1,162 @@
+use std::{collections::BTreeMap, path::Path, fs::{self, File}, io::BufReader, process::Command, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+use crate::command::command_output::command_output;
+
+use super::corpus::Mode;
+
+/// Self time and invocations of one query, summed over every crate of a
+/// build.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct QueryTotals {
+    pub self_time: Duration,
+    pub invocations: u64
+}
+
+// The part of the JSON `summarize summarize --json` writes that is used.
+#[derive(Deserialize)]
+struct Summary {
+    query_data: Vec<QueryData>
+}
+
+#[derive(Deserialize)]
+struct QueryData {
+    label: String,
+    self_time: Duration,
+    invocation_count: u64
+}
+
+/// Summarizes every `.mm_profdata` file rustc wrote into `dir` with
+/// measureme's `summarize` tool and sums the queries over them. The profile
+/// of the `___` crate cargo compiles to probe the target is left out.
+pub fn summarize_dir(summarize: &Path, dir: &Path) -> anyhow::Result<BTreeMap<String, QueryTotals>> {
+    let mut profiles = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_probe = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("___-"));
+        if path.extension().is_some_and(|extension| extension == "mm_profdata") && !is_probe {
+            profiles.push(path);
+        }
+    }
+    if profiles.is_empty() {
+        return Err(anyhow::anyhow!("rustc wrote no .mm_profdata file into {:?}, does the toolchain support -Zself-profile?", dir));
+    }
+
+    let mut totals: BTreeMap<String, QueryTotals> = BTreeMap::new();
+    for profile in profiles {
+        command_output(Command::new(summarize).arg("summarize").arg("--json").arg(&profile))
+            .map_err(|err| anyhow::anyhow!("Fail to summarize {:?}, is measureme's summarize installed? pass --summarize\n{}", profile, err))?;
+        let json = profile.with_extension("json");
+        let summary: Summary = serde_json::from_reader(BufReader::new(File::open(&json)?))
+            .map_err(|err| anyhow::anyhow!("Fail to parse {:?}\n{}", json, err))?;
+        for query in summary.query_data {
+            let total = totals.entry(query.label).or_default();
+            total.self_time += query.self_time;
+            total.invocations += query.invocation_count;
+        }
+    }
+    Ok(totals)
+}
+
+/// How one query changed from `_old` to `_cur`.
+#[derive(Serialize, Debug)]
+pub struct QueryDiff {
+    pub query: String,
+    pub old_self_secs: f64,
+    pub cur_self_secs: f64,
+    pub change_secs: f64,
+    // Missing when the query did not run on `_old`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_pct: Option<f64>,
+    pub old_invocations: u64,
+    pub cur_invocations: u64
+}
+
+/// The query diff of one crate and mode of a context.
+#[derive(Serialize, Debug)]
+pub struct ProfileDiff {
+    pub title: String,
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub mode: Mode,
+    pub old_self_secs: f64,
+    pub cur_self_secs: f64,
+    pub queries: Vec<QueryDiff>
+}
+
+impl ProfileDiff {
+    pub fn new(title: &str, krate: &str, mode: Mode, old: &BTreeMap<String, QueryTotals>, cur: &BTreeMap<String, QueryTotals>) -> Self {
+        let total = |totals: &BTreeMap<String, QueryTotals>| totals.values().map(|query| query.self_time.as_secs_f64()).sum();
+        ProfileDiff {
+            title: String::from(title),
+            krate: String::from(krate),
+            mode,
+            old_self_secs: total(old),
+            cur_self_secs: total(cur),
+            queries: diff_queries(old, cur)
+        }
+    }
+
+    /// Prints the `n` queries whose self time changed the most.
+    pub fn print_top(&self, n: usize) {
+        println!(
+            "{} {} {}: {:.3}s -> {:.3}s self time in queries",
+            self.title, self.krate, self.mode, self.old_self_secs, self.cur_self_secs
+        );
+        let width = self.queries.iter().take(n).map(|query| query.query.len()).max().unwrap_or(0);
+        for query in self.queries.iter().take(n) {
+            println!(
+                "  {:<width$}  {:>9.3}s -> {:>9.3}s  {:>+9.3}s {:>9}  {:>8} -> {:>8} invocations",
+                query.query, query.old_self_secs, query.cur_self_secs, query.change_secs,
+                query.change_pct.map(|pct| format!("({:+.1}%)", pct)).unwrap_or_default(),
+                query.old_invocations, query.cur_invocations,
+                width = width
+            );
+        }
+    }
+}
+
+/// Every query of either side, largest change of self time first.
+fn diff_queries(old: &BTreeMap<String, QueryTotals>, cur: &BTreeMap<String, QueryTotals>) -> Vec<QueryDiff> {
+    let mut labels: Vec<&String> = old.keys().chain(cur.keys()).collect();
+    labels.sort();
+    labels.dedup();
+
+    let mut queries: Vec<QueryDiff> = labels.into_iter()
+        .map(|label| {
+            let old = old.get(label).copied().unwrap_or_default();
+            let cur = cur.get(label).copied().unwrap_or_default();
+            let (old_self_secs, cur_self_secs) = (old.self_time.as_secs_f64(), cur.self_time.as_secs_f64());
+            QueryDiff {
+                query: label.clone(),
+                old_self_secs,
+                cur_self_secs,
+                change_secs: cur_self_secs - old_self_secs,
+                change_pct: (old_self_secs > 0.0).then(|| 100.0 * (cur_self_secs - old_self_secs) / old_self_secs),
+                old_invocations: old.invocations,
+                cur_invocations: cur.invocations
+            }
+        })
+        .collect();
+    queries.sort_by(|a, b| b.change_secs.abs().total_cmp(&a.change_secs.abs()));
+    queries
+}
+
+#[test]
+fn test_diff_queries() {
+    let totals = |queries: &[(&str, u64, u64)]| queries.iter()
+        .map(|(label, millis, invocations)| {
+            (String::from(*label), QueryTotals { self_time: Duration::from_millis(*millis), invocations: *invocations })
+        })
+        .collect::<BTreeMap<_, _>>();
+    let old = totals(&[("typeck", 500, 10), ("hir_crate", 100, 1), ("coherent_trait", 300, 4)]);
+    let cur = totals(&[("typeck", 520, 10), ("coherent_trait", 100, 4), ("hir_owner", 50, 12)]);
+
+    let queries = diff_queries(&old, &cur);
+    let labels: Vec<&str> = queries.iter().map(|query| query.query.as_str()).collect();
+    assert_eq!(labels, ["coherent_trait", "hir_crate", "hir_owner", "typeck"]);
+    assert!((queries[0].change_pct.unwrap() + 66.666).abs() < 0.01);
+    assert_eq!(queries[1].cur_invocations, 0);
+    assert_eq!(queries[2].change_pct, None);
+}
@@ -0,0 +1,50 @@
+use std::{path::Path, fs::File, io::BufReader};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{report, roller::stash::Side};
+
+use super::corpus::Mode;
+
+/// One compilation of a corpus crate with the toolchain of one side of a
+/// context.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Measurement {
+    pub title: String,
+    pub side: Side,
+    pub hash: String,
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub mode: Mode,
+    pub run: usize,
+    pub success: bool,
+    pub wall_secs: f64,
+    pub user_secs: f64,
+    pub sys_secs: f64,
+    pub max_rss_kb: u64,
+    // Counted with `--perf-counters`, when the kernel allows it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instructions: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cycles: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_misses: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_clock_secs: Option<f64>
+}
+
+/// Everything `bench` measured, as stored in `results.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct BenchResults {
+    pub measurements: Vec<Measurement>
+}
+
+impl BenchResults {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        report::write_json(path, self)
+    }
+}
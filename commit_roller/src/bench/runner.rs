@@ -0,0 +1,284 @@
+use std::{collections::BTreeMap, env, path::{Path, PathBuf}, fs::{self, OpenOptions}, process::{Command, Stdio}};
+
+use crate::{command::{interrupt, perf::Counters, rusage::run_measured}, report, roller::{gitlog::{short_hash, LogContext}, stash::{parse_commit_context_json, Side}}};
+
+use super::{corpus::{Corpus, CorpusCrate, Mode}, profile::{self, ProfileDiff, QueryTotals}, results::{BenchResults, Measurement}, toolchain::Toolchain};
+
+pub struct BenchOptions {
+    pub runs: usize,
+    pub modes: Vec<Mode>,
+    // Only bench the context with this title.
+    pub title: Option<String>,
+    // Whether to also compile once per side with `-Zself-profile` and diff
+    // the queries, using measureme's `summarize` tool.
+    pub self_profile: bool,
+    pub summarize: PathBuf,
+    // Whether to count hardware events of every compilation.
+    pub perf_counters: bool
+}
+
+/// Compiles every crate of `corpus` in every mode with the toolchains
+/// `roll_back` installed under `install_root` for both sides of each context,
+/// `runs` times each, and records the resources used, and with
+/// `perf_counters` the instructions, cycles, branch misses and task clock, into
+/// `<out_dir>/results.json`. Runs of the two sides alternate so that drift of
+/// the machine affects both alike. With `self_profile`, every crate is then
+/// compiled once more per side with `-Zself-profile`, outside of the timed
+/// runs, and the per-query diffs go to `<out_dir>/self_profile.json`.
+/// Returns whether every compilation succeeded.
+pub fn bench(commit_context_json: &Path, install_root: &Path, corpus: &Path, out_dir: &Path, options: &BenchOptions) -> bool {
+    let contexts = match parse_commit_context_json(commit_context_json) {
+        Ok(contexts) => contexts,
+        Err(err) => {
+            eprintln!("Fail to parse commit_context_json file {:?}\n{}", commit_context_json, err);
+            return false;
+        }
+    };
+    let corpus = match Corpus::load(corpus) {
+        Ok(corpus) => corpus,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    if let Err(err) = fs::create_dir_all(out_dir.join("logs")) {
+        eprintln!("Fail to create out dir {:?}\n{}", out_dir, err);
+        return false;
+    }
+
+    if options.perf_counters {
+        // Events the kernel refuses are left out of the results.
+        for (event, err) in Counters::open().unavailable {
+            eprintln!("perf counter {} is unavailable and will not be recorded\n{}", event, err);
+            if err.kind() == std::io::ErrorKind::PermissionDenied {
+                eprintln!("check /proc/sys/kernel/perf_event_paranoid, counting needs it at 2 or lower");
+            }
+        }
+    }
+
+    let results_path = out_dir.join("results.json");
+    let mut results = BenchResults::default();
+    let profile_path = out_dir.join("self_profile.json");
+    let mut profiles = Vec::new();
+    let mut all_ok = true;
+
+    let contexts = contexts.iter()
+        .filter(|context| options.title.as_ref().is_none_or(|title| &context.title == title));
+    for context in contexts {
+        let toolchains = match [Side::Old, Side::Cur].map(|side| Toolchain::find(install_root, context, side)) {
+            [Ok(old), Ok(cur)] => [old, cur],
+            [old, cur] => {
+                for err in [old.err(), cur.err()].into_iter().flatten() {
+                    eprintln!("Fail to bench {:?}\n{}", context.title, err);
+                }
+                all_ok = false;
+                continue;
+            }
+        };
+
+        for krate in &corpus.crates {
+            for mode in &options.modes {
+                for run in 0..options.runs {
+                    for toolchain in &toolchains {
+                        if interrupt::is_interrupted() {
+                            return false;
+                        }
+                        let measurement = match compile(toolchain, context, krate, *mode, run, out_dir, options.perf_counters) {
+                            Ok(measurement) => measurement,
+                            Err(err) => {
+                                eprintln!("Fail to compile {} with {:?} {}\n{}", krate.name, context.title, toolchain.side.name(), err);
+                                all_ok = false;
+                                continue;
+                            }
+                        };
+                        println!(
+                            "{} {} {} {} #{}: {:.2}s wall, {:.2}s user, {:.2}s sys, {} KB max rss{}{}",
+                            context.title, short_hash(toolchain.side.hash(context)), krate.name, mode, run,
+                            measurement.wall_secs, measurement.user_secs, measurement.sys_secs, measurement.max_rss_kb,
+                            measurement.instructions.map(|instructions| format!(", {} instructions", instructions)).unwrap_or_default(),
+                            if measurement.success { "" } else { " (failed)" }
+                        );
+                        all_ok &= measurement.success;
+                        results.measurements.push(measurement);
+
+                        // Saved as it goes, so an interrupted run keeps what it measured.
+                        if let Err(err) = results.save(&results_path) {
+                            eprintln!("Fail to write results to {:?}\n{}", results_path, err);
+                        }
+                    }
+                }
+            }
+        }
+
+        if !options.self_profile {
+            continue;
+        }
+        for krate in &corpus.crates {
+            for mode in &options.modes {
+                if interrupt::is_interrupted() {
+                    return false;
+                }
+                let [old, cur] = &toolchains;
+                let diff = self_profile(old, context, krate, *mode, out_dir, &options.summarize)
+                    .and_then(|old| Ok((old, self_profile(cur, context, krate, *mode, out_dir, &options.summarize)?)))
+                    .map(|(old, cur)| ProfileDiff::new(&context.title, &krate.name, *mode, &old, &cur));
+                match diff {
+                    Ok(diff) => {
+                        diff.print_top(10);
+                        profiles.push(diff);
+                        if let Err(err) = report::write_json(&profile_path, &profiles) {
+                            eprintln!("Fail to write self profile diffs to {:?}\n{}", profile_path, err);
+                        }
+                    },
+                    Err(err) => {
+                        eprintln!("Fail to self profile {} with {:?}\n{}", krate.name, context.title, err);
+                        all_ok = false;
+                    }
+                }
+            }
+        }
+    }
+
+    println!("succesfully write {} measurements to {:?}", results.measurements.len(), results_path);
+    if options.self_profile {
+        println!("succesfully write {} self profile diffs to {:?}", profiles.len(), profile_path);
+    }
+    all_ok
+}
+
+/// Compiles `krate` from scratch once.
+fn compile(toolchain: &Toolchain, context: &LogContext, krate: &CorpusCrate, mode: Mode, run: usize, out_dir: &Path, perf_counters: bool) -> anyhow::Result<Measurement> {
+    let name = job_name(toolchain, context, krate);
+    let log_path = out_dir.join("logs").join(format!("{}.log", name));
+    let mut cmd = cargo_command(toolchain, krate, mode, &out_dir.join("target").join(&name).join(mode.to_string()), &log_path)?;
+    // Opened last, as they count every command spawned after them.
+    let counters = perf_counters.then(Counters::open);
+    let usage = run_measured(&mut cmd)
+        .map_err(|err| anyhow::anyhow!("{}\nlog={}", err, log_path.display()))?;
+    let counts = counters.map(|counters| counters.read()).unwrap_or_default();
+
+    Ok(Measurement {
+        title: context.title.clone(),
+        side: toolchain.side,
+        hash: String::from(toolchain.side.hash(context)),
+        krate: krate.name.clone(),
+        mode,
+        run,
+        success: usage.status.success(),
+        wall_secs: usage.wall.as_secs_f64(),
+        user_secs: usage.user.as_secs_f64(),
+        sys_secs: usage.sys.as_secs_f64(),
+        max_rss_kb: usage.max_rss,
+        instructions: counts.instructions,
+        cycles: counts.cycles,
+        branch_misses: counts.branch_misses,
+        task_clock_secs: counts.task_clock_ns.map(|ns| ns as f64 / 1e9)
+    })
+}
+
+/// Compiles `krate` from scratch with `-Zself-profile` and sums the queries
+/// of the profiles rustc wrote for it and its dependencies.
+fn self_profile(toolchain: &Toolchain, context: &LogContext, krate: &CorpusCrate, mode: Mode, out_dir: &Path, summarize: &Path) -> anyhow::Result<BTreeMap<String, QueryTotals>> {
+    let name = job_name(toolchain, context, krate);
+    let profile_dir = out_dir.join("self_profile").join(&name).join(mode.to_string());
+    if profile_dir.exists() {
+        fs::remove_dir_all(&profile_dir)?;
+    }
+    fs::create_dir_all(&profile_dir)?;
+
+    let log_path = out_dir.join("logs").join(format!("{}_self_profile.log", name));
+    let mut cmd = cargo_command(toolchain, krate, mode, &out_dir.join("target").join(&name).join(format!("{}_self_profile", mode)), &log_path)?;
+    add_self_profile(&mut cmd, krate, &profile_dir);
+    let usage = run_measured(&mut cmd)
+        .map_err(|err| anyhow::anyhow!("{}\nlog={}", err, log_path.display()))?;
+    if !usage.status.success() {
+        return Err(anyhow::anyhow!("compilation failed with {}\nlog={}", usage.status, log_path.display()));
+    }
+    println!("succesfully self profile {} {} with {} {}", krate.name, mode, short_hash(toolchain.side.hash(context)), toolchain.side.name());
+
+    profile::summarize_dir(summarize, &profile_dir)
+}
+
+/// Adds `-Zself-profile=<profile_dir>` to the flags cargo passes to rustc for
+/// the timed builds of `krate`. Cargo takes them from the first that is set
+/// of `CARGO_ENCODED_RUSTFLAGS`, `RUSTFLAGS` (each of the crate's env or else
+/// inherited) and its config files, so the flag is added to that source.
+fn add_self_profile(cmd: &mut Command, krate: &CorpusCrate, profile_dir: &Path) {
+    let flag = format!("-Zself-profile={}", profile_dir.display());
+    let var = |name: &str| krate.env.get(name).cloned().or_else(|| env::var(name).ok());
+    let from_env = match var("CARGO_ENCODED_RUSTFLAGS") {
+        Some(encoded) => Some(encoded.split('\u{1f}').filter(|flag| !flag.is_empty()).map(String::from).collect()),
+        None => var("RUSTFLAGS").map(|flags| flags.split_whitespace().map(String::from).collect::<Vec<_>>()),
+    };
+    match from_env {
+        // Through the encoded form, which takes precedence over `RUSTFLAGS`,
+        // so that paths with spaces survive.
+        Some(mut rustflags) => {
+            rustflags.push(flag);
+            cmd.env("CARGO_ENCODED_RUSTFLAGS", rustflags.join("\u{1f}"));
+        }
+        // Arrays of `--config` are appended to those of the config files.
+        None => {
+            cmd.arg("--config").arg(format!("build.rustflags=[{}]", toml::Value::String(flag)));
+        }
+    }
+    // Toolchains built from a stable or beta branch reject -Z flags otherwise.
+    cmd.env("RUSTC_BOOTSTRAP", "1");
+}
+
+pub fn job_name(toolchain: &Toolchain, context: &LogContext, krate: &CorpusCrate) -> String {
+    format!(
+        "{}_{}_{}_{}",
+        context.title.replace(' ', "_"), short_hash(toolchain.side.hash(context)), toolchain.side.name(), krate.name
+    )
+}
+
+/// A cargo invocation building `krate` in `mode` into the emptied
+/// `target_dir`, with its output appended to `log_path`.
+fn cargo_command(toolchain: &Toolchain, krate: &CorpusCrate, mode: Mode, target_dir: &Path, log_path: &Path) -> anyhow::Result<Command> {
+    if target_dir.exists() {
+        fs::remove_dir_all(target_dir)?;
+    }
+    let log = OpenOptions::new().create(true).append(true).open(log_path)?;
+
+    let mut cmd = Command::new(&toolchain.cargo);
+    cmd.current_dir(&krate.path)
+        .args(mode.cargo_args())
+        .args(&krate.args)
+        .envs(&krate.env)
+        .env("RUSTC", &toolchain.rustc)
+        .env("CARGO_TARGET_DIR", target_dir)
+        .env("CARGO_INCREMENTAL", "0")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    Ok(cmd)
+}
+
+#[test]
+fn test_self_profile_keeps_config_rustflags() {
+    let dir = std::env::temp_dir().join(format!("commit_roller_self_profile_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("krate").join("src")).unwrap();
+    fs::create_dir_all(dir.join("krate").join(".cargo")).unwrap();
+    fs::write(dir.join("krate").join("Cargo.toml"), "[package]\nname = \"krate\"\nversion = \"0.1.0\"\nedition = \"2021\"\n").unwrap();
+    fs::write(dir.join("krate").join(".cargo").join("config.toml"), "[build]\nrustflags = [\"--cfg\", \"roller_config\"]\n").unwrap();
+    fs::write(dir.join("krate").join("src").join("lib.rs"), "#[cfg(not(roller_config))]\ncompile_error!(\"rustflags of the config were dropped\");\n").unwrap();
+
+    let toolchain = Toolchain {
+        side: Side::Cur,
+        rustc: PathBuf::from("rustc"),
+        cargo: env::var_os("CARGO").map_or_else(|| PathBuf::from("cargo"), PathBuf::from)
+    };
+    let krate = CorpusCrate { name: String::from("krate"), path: dir.join("krate"), args: vec![], env: BTreeMap::new() };
+    let profile_dir = dir.join("profile dir");
+    fs::create_dir_all(&profile_dir).unwrap();
+
+    let log_path = dir.join("build.log");
+    let mut cmd = cargo_command(&toolchain, &krate, Mode::Check, &dir.join("target"), &log_path).unwrap();
+    add_self_profile(&mut cmd, &krate, &profile_dir);
+    assert!(cmd.status().unwrap().success(), "{}", fs::read_to_string(&log_path).unwrap_or_default());
+    assert!(fs::read_dir(&profile_dir).unwrap().any(|entry| entry.unwrap().path().extension().is_some_and(|extension| extension == "mm_profdata")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,363 @@
+use std::{collections::BTreeMap, path::{Path, PathBuf}, fs, process::Command};
+
+use object::{read::archive::ArchiveFile, FileKind, Object, ObjectSection, ObjectSymbol};
+use regex::Regex;
+use serde::{Serialize, Deserialize};
+
+use crate::{command::{command_output::command_output, interrupt}, report, roller::{gitlog::{short_hash, LogContext}, stash::{parse_commit_context_json, Side}}};
+
+use super::{corpus::{Corpus, CorpusCrate, Mode}, runner::job_name, toolchain::Toolchain};
+
+/// Sections whose sizes are reported, with their `.<section>.*` variants
+/// counted in.
+const SECTIONS: [&str; 3] = [".text", ".data", ".rodata"];
+
+pub struct SizeOptions {
+    pub modes: Vec<Mode>,
+    // Growth in percent above which a size is flagged as a regression.
+    pub threshold_pct: f64,
+    // Only compare the context with this title.
+    pub title: Option<String>
+}
+
+/// Sizes of one library or executable cargo produced.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ArtifactSize {
+    pub bytes: u64,
+    // Summed over the objects of an archive. Empty when the file is not an
+    // object file or archive.
+    pub sections: BTreeMap<String, u64>,
+    // Defined symbols.
+    pub symbols: u64
+}
+
+/// Whether a size is only there on one side, e.g. a section the other side
+/// doesn't have.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SizeStatus {
+    Added,
+    Removed
+}
+
+/// How one size of an artifact changed from `_old` to `_cur`.
+#[derive(Serialize, Debug)]
+pub struct SizeChange {
+    pub metric: String,
+    pub old: u64,
+    pub cur: u64,
+    // In percent of `_old`. Missing when `_old` is zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_pct: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<SizeStatus>,
+    // Anything added counts as grown past the threshold.
+    pub regression: bool
+}
+
+#[derive(Serialize, Debug)]
+pub struct ArtifactComparison {
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub mode: Mode,
+    // File name without the metadata hash, e.g. `libregex.rlib`.
+    pub artifact: String,
+    // Missing when only one side produced the artifact.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old: Option<ArtifactSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cur: Option<ArtifactSize>,
+    pub changes: Vec<SizeChange>
+}
+
+#[derive(Serialize, Debug)]
+pub struct SizeReport {
+    pub title: String,
+    pub hash_old: String,
+    pub hash_cur: String,
+    pub regressions: usize,
+    pub artifacts: Vec<ArtifactComparison>
+}
+
+/// Builds every crate of `corpus` with the toolchains `roll_back` installed
+/// for both sides of each context and compares the sizes of the libraries
+/// and executables produced: file sizes, sizes of `SECTIONS` and numbers of
+/// defined symbols. Sizes that grew by more than `threshold_pct` are flagged
+/// as regressions. The comparisons go to `<out_dir>/size.json`. Returns
+/// whether every build succeeded.
+pub fn size_report(commit_context_json: &Path, install_root: &Path, corpus: &Path, out_dir: &Path, options: &SizeOptions) -> bool {
+    let contexts = match parse_commit_context_json(commit_context_json) {
+        Ok(contexts) => contexts,
+        Err(err) => {
+            eprintln!("Fail to parse commit_context_json file {:?}\n{}", commit_context_json, err);
+            return false;
+        }
+    };
+    let corpus = match Corpus::load(corpus) {
+        Ok(corpus) => corpus,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+    if let Err(err) = fs::create_dir_all(out_dir) {
+        eprintln!("Fail to create out dir {:?}\n{}", out_dir, err);
+        return false;
+    }
+    // `cargo check` leaves no library or executable behind.
+    let modes: Vec<Mode> = options.modes.iter().copied().filter(|mode| *mode != Mode::Check).collect();
+    if modes.len() < options.modes.len() {
+        eprintln!("skipping mode check, which produces no artifacts");
+    }
+
+    let report_path = out_dir.join("size.json");
+    let mut reports = Vec::new();
+    let mut all_ok = true;
+
+    let contexts = contexts.iter()
+        .filter(|context| options.title.as_ref().is_none_or(|title| &context.title == title));
+    for context in contexts {
+        let toolchains = match [Side::Old, Side::Cur].map(|side| Toolchain::find(install_root, context, side)) {
+            [Ok(old), Ok(cur)] => [old, cur],
+            [old, cur] => {
+                for err in [old.err(), cur.err()].into_iter().flatten() {
+                    eprintln!("Fail to compare sizes of {:?}\n{}", context.title, err);
+                }
+                all_ok = false;
+                continue;
+            }
+        };
+
+        let mut report = SizeReport {
+            title: context.title.clone(),
+            hash_old: context.hash_old.clone(),
+            hash_cur: context.hash_cur.clone(),
+            regressions: 0,
+            artifacts: Vec::new()
+        };
+        for krate in &corpus.crates {
+            for mode in &modes {
+                if interrupt::is_interrupted() {
+                    return false;
+                }
+                let [old, cur] = &toolchains;
+                let sizes = build(old, context, krate, *mode, out_dir)
+                    .and_then(|old| Ok((old, build(cur, context, krate, *mode, out_dir)?)));
+                match sizes {
+                    Ok((old, cur)) => report.artifacts.extend(compare_artifacts(&krate.name, *mode, old, cur, options.threshold_pct)),
+                    Err(err) => {
+                        eprintln!("Fail to build {} with {:?}\n{}", krate.name, context.title, err);
+                        all_ok = false;
+                    }
+                }
+            }
+        }
+        report.regressions = report.artifacts.iter()
+            .flat_map(|artifact| &artifact.changes)
+            .filter(|change| change.regression)
+            .count();
+        print_report(&report);
+        reports.push(report);
+    }
+
+    match report::write_json(&report_path, &reports) {
+        Ok(()) => println!("succesfully write sizes of {} contexts to {:?}", reports.len(), report_path),
+        Err(err) => {
+            eprintln!("Fail to write sizes to {:?}\n{}", report_path, err);
+            return false;
+        }
+    }
+    all_ok
+}
+
+// The part of cargo's JSON messages that is used.
+#[derive(Deserialize)]
+struct Message {
+    reason: String,
+    #[serde(default)]
+    manifest_path: PathBuf,
+    #[serde(default)]
+    filenames: Vec<PathBuf>
+}
+
+/// Builds `krate` from scratch and measures the libraries and executables of
+/// its own package, by file name without the metadata hash.
+fn build(toolchain: &Toolchain, context: &LogContext, krate: &CorpusCrate, mode: Mode, out_dir: &Path) -> anyhow::Result<BTreeMap<String, ArtifactSize>> {
+    let target_dir = out_dir.join("target").join(job_name(toolchain, context, krate)).join(mode.to_string());
+    if target_dir.exists() {
+        fs::remove_dir_all(&target_dir)?;
+    }
+
+    let mut cmd = Command::new(&toolchain.cargo);
+    cmd.current_dir(&krate.path)
+        .args(mode.cargo_args())
+        .arg("--message-format=json-render-diagnostics")
+        .args(&krate.args)
+        .envs(&krate.env)
+        .env("RUSTC", &toolchain.rustc)
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .env("CARGO_INCREMENTAL", "0");
+    let output = command_output(&mut cmd)?;
+    println!("succesfully build {} {} with {} {}", krate.name, mode, short_hash(toolchain.side.hash(context)), toolchain.side.name());
+
+    let package_dir = krate.path.canonicalize()?;
+    let hash = Regex::new(r"-[0-9a-f]{16}(\.|$)").unwrap();
+    let mut sizes = BTreeMap::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Message>(line) else {
+            continue;
+        };
+        if message.reason != "compiler-artifact" || !message.manifest_path.starts_with(&package_dir) {
+            continue;
+        }
+        for path in message.filenames {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            // Metadata and dependency info are not what ends up linked.
+            if name.ends_with(".rmeta") || name.ends_with(".d") {
+                continue;
+            }
+            let size = measure(&path).map_err(|err| anyhow::anyhow!("Fail to measure {:?}\n{}", path, err))?;
+            sizes.insert(hash.replace(&name, "$1").into_owned(), size);
+        }
+    }
+    if sizes.is_empty() {
+        return Err(anyhow::anyhow!("cargo produced no library or executable for {}", krate.name));
+    }
+    Ok(sizes)
+}
+
+fn measure(path: &Path) -> anyhow::Result<ArtifactSize> {
+    let data = fs::read(path)?;
+    let mut size = ArtifactSize { bytes: data.len() as u64, ..Default::default() };
+    match FileKind::parse(&*data) {
+        Ok(FileKind::Archive) => {
+            let archive = ArchiveFile::parse(&*data)?;
+            for member in archive.members() {
+                let member = member?;
+                // The metadata member `lib.rmeta` of an rlib is not an object file.
+                if let Ok(file) = object::File::parse(member.data(&*data)?) {
+                    add_object(&mut size, &file);
+                }
+            }
+        },
+        Ok(_) => {
+            if let Ok(file) = object::File::parse(&*data) {
+                add_object(&mut size, &file);
+            }
+        },
+        Err(_) => {},
+    }
+    Ok(size)
+}
+
+fn add_object(size: &mut ArtifactSize, file: &object::File) {
+    for section in file.sections() {
+        let Ok(name) = section.name() else {
+            continue;
+        };
+        let reported = SECTIONS.iter().find(|reported| name == **reported || name.strip_prefix(**reported).is_some_and(|rest| rest.starts_with('.')));
+        if let Some(reported) = reported {
+            *size.sections.entry(String::from(*reported)).or_default() += section.size();
+        }
+    }
+    size.symbols += file.symbols().filter(|symbol| symbol.is_definition()).count() as u64;
+}
+
+fn compare_artifacts(krate: &str, mode: Mode, mut old: BTreeMap<String, ArtifactSize>, mut cur: BTreeMap<String, ArtifactSize>, threshold_pct: f64) -> Vec<ArtifactComparison> {
+    let mut names: Vec<String> = old.keys().chain(cur.keys()).cloned().collect();
+    names.sort();
+    names.dedup();
+
+    names.into_iter()
+        .map(|artifact| {
+            let (old, cur) = (old.remove(&artifact), cur.remove(&artifact));
+            let changes = match (&old, &cur) {
+                (Some(old), Some(cur)) => size_changes(old, cur, threshold_pct),
+                _ => Vec::new(),
+            };
+            ArtifactComparison { krate: String::from(krate), mode, artifact, old, cur, changes }
+        })
+        .collect()
+}
+
+fn size_changes(old: &ArtifactSize, cur: &ArtifactSize, threshold_pct: f64) -> Vec<SizeChange> {
+    let mut metrics = vec![(String::from("bytes"), old.bytes, cur.bytes)];
+    for section in SECTIONS {
+        let (old, cur) = (old.sections.get(section), cur.sections.get(section));
+        if old.is_some() || cur.is_some() {
+            metrics.push((String::from(section), old.copied().unwrap_or_default(), cur.copied().unwrap_or_default()));
+        }
+    }
+    metrics.push((String::from("symbols"), old.symbols, cur.symbols));
+
+    metrics.into_iter()
+        .map(|(metric, old, cur)| {
+            let change_pct = (old != 0).then(|| 100.0 * (cur as f64 - old as f64) / old as f64);
+            let status = match (old, cur) {
+                (0, 0) => None,
+                (0, _) => Some(SizeStatus::Added),
+                (_, 0) => Some(SizeStatus::Removed),
+                _ => None,
+            };
+            let regression = match change_pct {
+                Some(change_pct) => change_pct > threshold_pct,
+                None => status == Some(SizeStatus::Added),
+            };
+            SizeChange { metric, old, cur, change_pct, status, regression }
+        })
+        .collect()
+}
+
+fn print_report(report: &SizeReport) {
+    println!(
+        "{} {} -> {}: {} size regressions",
+        report.title, short_hash(&report.hash_old), short_hash(&report.hash_cur), report.regressions
+    );
+    for artifact in &report.artifacts {
+        match (&artifact.old, &artifact.cur) {
+            (Some(_), Some(_)) => {},
+            (old, _) => {
+                println!("  {} {} {}: only built by {}", artifact.krate, artifact.mode, artifact.artifact, if old.is_some() { "old" } else { "cur" });
+                continue;
+            }
+        }
+        println!("  {} {} {}", artifact.krate, artifact.mode, artifact.artifact);
+        for change in &artifact.changes {
+            let change_pct = match (change.status, change.change_pct) {
+                (Some(SizeStatus::Added), _) => String::from("added"),
+                (Some(SizeStatus::Removed), _) => String::from("removed"),
+                (None, Some(change_pct)) => format!("{:+.2}%", change_pct),
+                (None, None) => String::new(),
+            };
+            println!(
+                "    {:<8} {:>12} -> {:>12} {:>10}{}",
+                change.metric, change.old, change.cur, change_pct,
+                if change.regression { "  REGRESSION" } else { "" }
+            );
+        }
+    }
+}
+
+#[test]
+fn test_size_changes() {
+    let size = |bytes, text, symbols| ArtifactSize {
+        bytes,
+        sections: BTreeMap::from([(String::from(".text"), text)]),
+        symbols
+    };
+    let changes = size_changes(&size(1000, 400, 10), &size(1005, 420, 10), 1.0);
+    let flagged: Vec<(&str, bool)> = changes.iter().map(|change| (change.metric.as_str(), change.regression)).collect();
+    assert_eq!(flagged, [("bytes", false), (".text", true), ("symbols", false)]);
+    assert_eq!(changes[1].change_pct, Some(5.0));
+
+    // A section only one side has is added or removed rather than infinitely
+    // grown, which JSON can't hold.
+    let mut rodata = size(1000, 400, 10);
+    rodata.sections.insert(String::from(".rodata"), 64);
+    let added = size_changes(&size(1000, 400, 10), &rodata, 1.0);
+    assert_eq!((added[2].metric.as_str(), added[2].change_pct, added[2].status, added[2].regression), (".rodata", None, Some(SizeStatus::Added), true));
+    let removed = size_changes(&rodata, &size(1000, 400, 10), 1.0);
+    assert_eq!((removed[2].change_pct, removed[2].status, removed[2].regression), (Some(-100.0), Some(SizeStatus::Removed), false));
+    let json = serde_json::to_string(&added).unwrap();
+    assert!(json.contains(r#""status":"added""#) && !json.contains("null"));
+}
@@ -0,0 +1,157 @@
+//! Summary statistics and Welch's t-test, enough to tell a change of compile
+//! time from noise without pulling in a statistics crate.
+
+pub fn mean(xs: &[f64]) -> f64 {
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+pub fn median(xs: &[f64]) -> f64 {
+    let mut sorted = xs.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Sample variance, `None` for fewer than two values.
+pub fn variance(xs: &[f64]) -> Option<f64> {
+    if xs.len() < 2 {
+        return None;
+    }
+    let mean = mean(xs);
+    Some(xs.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (xs.len() - 1) as f64)
+}
+
+pub fn stddev(xs: &[f64]) -> Option<f64> {
+    variance(xs).map(f64::sqrt)
+}
+
+/// Result of Welch's t-test of `b` against `a`.
+#[derive(Debug, Clone, Copy)]
+pub struct Welch {
+    // Confidence interval of `mean(b) - mean(a)`.
+    pub low: f64,
+    pub high: f64,
+    // Two-sided.
+    pub p_value: f64
+}
+
+/// Welch's t-test for a difference of means with unequal variances, with the
+/// `confidence` interval of the difference. `None` for fewer than two values
+/// on either side.
+pub fn welch(a: &[f64], b: &[f64], confidence: f64) -> Option<Welch> {
+    let (va, vb) = (variance(a)? / a.len() as f64, variance(b)? / b.len() as f64);
+    let diff = mean(b) - mean(a);
+    let se = (va + vb).sqrt();
+    if se == 0.0 {
+        let p_value = if diff == 0.0 { 1.0 } else { 0.0 };
+        return Some(Welch { low: diff, high: diff, p_value });
+    }
+
+    let df = (va + vb).powi(2)
+        / (va.powi(2) / (a.len() - 1) as f64 + vb.powi(2) / (b.len() - 1) as f64);
+    let t = diff / se;
+    let margin = t_quantile(1.0 - confidence, df) * se;
+    Some(Welch { low: diff - margin, high: diff + margin, p_value: t_p_value(t, df) })
+}
+
+/// Two-sided p-value of `t` under Student's t distribution.
+pub fn t_p_value(t: f64, df: f64) -> f64 {
+    incomplete_beta(df / 2.0, 0.5, df / (df + t * t))
+}
+
+/// The `t` whose two-sided p-value is `p`.
+fn t_quantile(p: f64, df: f64) -> f64 {
+    let (mut low, mut high) = (0.0, 1e3);
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if t_p_value(mid, df) > p {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    (low + high) / 2.0
+}
+
+/// Regularized incomplete beta function `I_x(a, b)`.
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    // The continued fraction converges fast only on this side.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function, by Lentz's method.
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        for coefficient in [
+            m * (b - m) * x / ((a + m2 - 1.0) * (a + m2)),
+            -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0)),
+        ] {
+            d = 1.0 + coefficient * d;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = 1.0 + coefficient / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            h *= d * c;
+        }
+        if (d * c - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    h
+}
+
+/// Lanczos approximation of `ln Γ(x)`.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146, -86.50532032941677, 24.01409824083091,
+        -1.231739572450155, 0.1208650973866179e-2, -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS.iter().enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| sum + c / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+#[test]
+fn test_welch() {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-3;
+    assert!(close(t_p_value(2.0, 10.0), 0.0734));
+    assert!(close(t_quantile(0.05, 10.0), 2.228));
+    assert!(close(median(&[3.0, 1.0, 2.0, 10.0]), 2.5));
+
+    let old = [10.0, 10.2, 9.9, 10.1, 10.0];
+    let cur = [11.0, 11.1, 10.9, 11.2, 11.0];
+    let change = welch(&old, &cur, 0.95).unwrap();
+    assert!(change.p_value < 0.001);
+    assert!(change.low > 0.8 && change.high < 1.2);
+    assert!(welch(&old, &cur[..1], 0.95).is_none());
+}
@@ -0,0 +1,25 @@
+use std::path::{Path, PathBuf};
+
+use crate::roller::{gitlog::LogContext, stash::{install_prefix, Side}};
+
+/// An installed toolchain of one side of a context.
+pub struct Toolchain {
+    pub side: Side,
+    pub rustc: PathBuf,
+    pub cargo: PathBuf
+}
+
+impl Toolchain {
+    pub fn find(install_root: &Path, context: &LogContext, side: Side) -> anyhow::Result<Self> {
+        let prefix = install_prefix(install_root, context, side);
+        let rustc = prefix.join("bin").join("rustc");
+        if !rustc.is_file() {
+            return Err(anyhow::anyhow!("no rustc installed at {:?}, run roll_back first", rustc));
+        }
+        // x.py only installs cargo for extended builds.
+        let cargo = Some(prefix.join("bin").join("cargo"))
+            .filter(|cargo| cargo.is_file())
+            .unwrap_or_else(|| PathBuf::from("cargo"));
+        Ok(Toolchain { side, rustc, cargo })
+    }
+}
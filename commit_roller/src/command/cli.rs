@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::{bench::{codegen::Emit, compare::Metric, corpus::Mode}, git::BackendKind, roller::workspace::WorkspaceStrategy};
+
 #[derive(Debug, clap::Parser)]
 #[clap(about, version, author)]
 pub struct Cli {
@@ -18,7 +20,14 @@ pub enum Commands {
         commits_json: PathBuf,
 
         #[clap(long = "out")]
-        out: PathBuf
+        out: PathBuf,
+
+        /// Flag pairs with more commits than this between them
+        #[clap(long = "max-distance", default_value = "200")]
+        max_distance: u64,
+
+        #[clap(long = "git-backend", value_enum, default_value = "cli")]
+        git_backend: BackendKind
     },
     RollBack {
         #[clap(long = "repo-dir")]
@@ -28,6 +37,225 @@ pub enum Commands {
         commit_context_json: PathBuf,
 
         #[clap(long = "out-dir")]
-        out_dir: PathBuf
+        out_dir: PathBuf,
+
+        /// Number of toolchains built at the same time
+        #[clap(long = "jobs", default_value = "1")]
+        jobs: usize,
+
+        /// GB to keep free on the filesystem of the out dir besides what --job-disk-gb reserves
+        #[clap(long = "min-free-gb", default_value = "0")]
+        min_free_gb: u64,
+
+        /// GB a single job is expected to take on disk
+        #[clap(long = "job-disk-gb", default_value = "0")]
+        job_disk_gb: u64,
+
+        /// Continue an interrupted run from the journal in the out dir
+        #[clap(long = "resume")]
+        resume: bool,
+
+        #[clap(flatten)]
+        build: BuildArgs
+    },
+    /// Find the first commit between `hash_old` and `hash_cur` of a context
+    /// for which the predicate sees the new behavior
+    Bisect {
+        #[clap(long = "repo-dir")]
+        repo_dir: PathBuf,
+
+        #[clap(long = "commit_id_json")]
+        commit_context_json: PathBuf,
+
+        /// Title of the context to bisect, may be left out if there is only one
+        #[clap(long = "title")]
+        title: Option<String>,
+
+        #[clap(long = "out-dir")]
+        out_dir: PathBuf,
+
+        /// Script run with the install prefix of a toolchain; exits 0 for the old behavior,
+        /// 125 when the commit can't be tested and 1 to 127 otherwise for the new one
+        #[clap(long = "predicate")]
+        predicate: PathBuf,
+
+        #[clap(flatten)]
+        build: BuildArgs
+    },
+    /// Compile a corpus of crates with the toolchains `roll_back` installed
+    /// for both sides of each context and record the resources used
+    Bench {
+        #[clap(long = "commit_id_json")]
+        commit_context_json: PathBuf,
+
+        /// `--install-root` of `roll_back`
+        #[clap(long = "install-root")]
+        install_root: PathBuf,
+
+        /// TOML file listing the crates to compile
+        #[clap(long = "corpus")]
+        corpus: PathBuf,
+
+        #[clap(long = "out-dir")]
+        out_dir: PathBuf,
+
+        /// Compilations of each crate, mode and toolchain
+        #[clap(long = "runs", default_value = "3")]
+        runs: usize,
+
+        #[clap(long = "modes", value_enum, value_delimiter = ',', default_value = "check,debug,release")]
+        modes: Vec<Mode>,
+
+        /// Only bench the context with this title
+        #[clap(long = "title")]
+        title: Option<String>,
+
+        /// Also compile each crate once per toolchain with `-Zself-profile` and diff the queries
+        #[clap(long = "self-profile")]
+        self_profile: bool,
+
+        /// measureme's `summarize` tool, looked up in `PATH` by default
+        #[clap(long = "summarize", default_value = "summarize")]
+        summarize: PathBuf,
+
+        /// Also count instructions, cycles, branch misses and task clock with perf_event_open
+        #[clap(long = "perf-counters")]
+        perf_counters: bool
+    },
+    /// Compile a source file or crate with the toolchains `roll_back` installed
+    /// for both sides of each context and diff the generated code per function
+    #[clap(alias = "diff-codegen")]
+    DiffCodegen {
+        #[clap(long = "commit_id_json")]
+        commit_context_json: PathBuf,
+
+        /// `--install-root` of `roll_back`
+        #[clap(long = "install-root")]
+        install_root: PathBuf,
+
+        /// Source file compiled with rustc, or crate directory compiled with `cargo rustc`
+        #[clap(long = "input")]
+        input: PathBuf,
+
+        #[clap(long = "out-dir")]
+        out_dir: PathBuf,
+
+        #[clap(long = "emit", value_enum, value_delimiter = ',', default_value = "mir,llvm-ir,asm")]
+        emit: Vec<Emit>,
+
+        #[clap(long = "opt-level", default_value = "3")]
+        opt_level: String,
+
+        /// Crate type of a source file input
+        #[clap(long = "crate-type", default_value = "lib")]
+        crate_type: String,
+
+        /// Edition of a source file input
+        #[clap(long = "edition", default_value = "2021")]
+        edition: String,
+
+        /// Extra rustc arguments, e.g. `--rustc-args=-Zmir-opt-level=3`
+        #[clap(long = "rustc-args", value_delimiter = ',', allow_hyphen_values = true)]
+        rustc_args: Vec<String>,
+
+        /// Only diff the context with this title
+        #[clap(long = "title")]
+        title: Option<String>
+    },
+    /// Build a corpus of crates with the toolchains `roll_back` installed for
+    /// both sides of each context and compare the sizes of what they produce
+    SizeReport {
+        #[clap(long = "commit_id_json")]
+        commit_context_json: PathBuf,
+
+        /// `--install-root` of `roll_back`
+        #[clap(long = "install-root")]
+        install_root: PathBuf,
+
+        /// TOML file listing the crates to build, as for `bench`
+        #[clap(long = "corpus")]
+        corpus: PathBuf,
+
+        #[clap(long = "out-dir")]
+        out_dir: PathBuf,
+
+        #[clap(long = "modes", value_enum, value_delimiter = ',', default_value = "release")]
+        modes: Vec<Mode>,
+
+        /// Growth in percent above which a size is flagged as a regression
+        #[clap(long = "threshold-pct", default_value = "1.0")]
+        threshold_pct: f64,
+
+        /// Only compare the context with this title
+        #[clap(long = "title")]
+        title: Option<String>
+    },
+    /// Compare the `_cur` and `_old` measurements of a `bench` run and tell
+    /// significant changes from noise
+    Compare {
+        /// `results.json` written by `bench`
+        #[clap(long = "results")]
+        results: PathBuf,
+
+        /// Where to write the comparisons, `compare.json` next to the results by default
+        #[clap(long = "out")]
+        out: Option<PathBuf>,
+
+        #[clap(long = "metric", value_enum, default_value = "wall")]
+        metric: Metric,
+
+        /// Significance level of Welch's t-test
+        #[clap(long = "alpha", default_value = "0.05")]
+        alpha: f64,
+
+        /// Smallest change of the mean, in percent, reported as a regression or improvement
+        #[clap(long = "threshold-pct", default_value = "1.0")]
+        threshold_pct: f64,
+
+        /// Only compare the context with this title
+        #[clap(long = "title")]
+        title: Option<String>
     }
-}
\ No newline at end of file
+}
+
+/// How toolchains are checked out, built and installed.
+#[derive(Debug, clap::Args)]
+pub struct BuildArgs {
+    #[clap(long = "git-backend", value_enum, default_value = "cli")]
+    pub git_backend: BackendKind,
+
+    #[clap(long = "workspace", value_enum, default_value = "worktree")]
+    pub workspace: WorkspaceStrategy,
+
+    /// Template of `config.toml` with `{{title}}`, `{{hash}}`, `{{side}}` and `{{prefix}}` placeholders
+    #[clap(long = "config-template")]
+    pub config_template: Option<PathBuf>,
+
+    /// Where toolchains are installed, `<out-dir>/targets` by default
+    #[clap(long = "install-root")]
+    pub install_root: Option<PathBuf>,
+
+    /// Build recipe preset (`rustc`, `cargo`, `make`, `cmake`) or a recipe TOML file
+    #[clap(long = "recipe", default_value = "rustc")]
+    pub recipe: String,
+
+    /// Python interpreter running x.py, python3/python/python2 from PATH by default
+    #[clap(long = "python")]
+    pub python: Option<PathBuf>,
+
+    /// Seconds after which a build step without a timeout of its own is killed
+    #[clap(long = "step-timeout")]
+    pub step_timeout: Option<u64>,
+
+    /// `{{jobs}}` of each build, the CPUs split evenly between jobs by default
+    #[clap(long = "build-jobs")]
+    pub build_jobs: Option<usize>,
+
+    /// Lines of a failing build step's log to show
+    #[clap(long = "log-tail", default_value = "20")]
+    pub log_tail: usize,
+
+    /// Remove the workspaces of jobs stopped by Ctrl-C
+    #[clap(long = "clean-interrupted")]
+    pub clean_interrupted: bool
+}
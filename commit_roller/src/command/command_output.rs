@@ -1,19 +1,151 @@
-use std::process::{self, Command, Stdio};
+use std::{fmt, path::Path, process::{self, Command, ExitStatus, Stdio}, sync::mpsc::{self, RecvTimeoutError}, thread, time::Duration};
 
-fn run_command_with_output(cmd: &mut Command) -> anyhow::Result<process::Output> {
-    use anyhow::Context;
-    use crate::command::read2;
-    let mut child = cmd
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .with_context(|| format!("failed to spawn process for cmd: {:?}", cmd))?;
+use super::log::CommandLog;
+
+/// How long a timed out command gets to exit after `SIGTERM` before its
+/// process group is sent `SIGKILL`.
+const KILL_GRACE: Duration = Duration::from_secs(10);
+
+/// Why a command didn't run to a successful end, kept inside the
+/// `anyhow::Error`s of this module so callers can tell them apart with
+/// `downcast_ref` or `CommandError::find`.
+#[derive(Debug)]
+pub enum CommandError {
+    Spawn { cmd: String, reason: String },
+    // `output` is whatever the command printed before it was killed, if kept.
+    TimedOut { cmd: String, timeout: Duration, output: String },
+    // Stopped, or never started, because `roll_back` got SIGINT/SIGTERM.
+    Interrupted { cmd: String },
+    Failed { status: ExitStatus, output: String }
+}
+
+impl CommandError {
+    /// The `CommandError` anywhere in the chain of `err`.
+    pub fn find(err: &anyhow::Error) -> Option<&CommandError> {
+        err.chain().find_map(|cause| cause.downcast_ref::<CommandError>())
+    }
+}
 
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Spawn { cmd, reason } =>
+                write!(f, "failed to spawn process for cmd: {}\n{}", cmd, reason),
+            CommandError::TimedOut { cmd, timeout, output } =>
+                write!(f, "timed out after {:?}: {}\n{}", timeout, cmd, output),
+            CommandError::Interrupted { cmd } =>
+                write!(f, "interrupted: {}", cmd),
+            CommandError::Failed { status, output } =>
+                write!(f, "expected success, got {}\n\n{}", status, output),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Runs `cmd` and collects its output. With a `timeout`, the command is
+/// terminated along with every process it spawned once it passes, failing
+/// with `CommandError::TimedOut`.
+pub fn run_command_with_timeout(cmd: &mut Command, timeout: Option<Duration>) -> anyhow::Result<process::Output> {
     let mut stdout = Vec::new();
     let mut stderr = Vec::new();
+    let status = run_command(cmd, timeout, &mut |is_stdout, buffer| {
+        if is_stdout {
+            stdout.extend_from_slice(buffer);
+        } else {
+            stderr.extend_from_slice(buffer);
+        }
+    });
+    let status = match status {
+        Ok(status) => status,
+        // Keep what it printed up to the kill, which is often why it hung.
+        Err(err) => return Err(match err.downcast::<CommandError>() {
+            Ok(CommandError::TimedOut { cmd, timeout, .. }) => CommandError::TimedOut {
+                cmd,
+                timeout,
+                output: format_output(&stdout, &stderr)
+            }.into(),
+            Ok(err) => err.into(),
+            Err(err) => err,
+        }),
+    };
+
+    Ok(process::Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+/// Like `run_command_with_timeout`, but streams the output into `log`
+/// instead of keeping it in memory.
+pub fn run_command_logged(cmd: &mut Command, timeout: Option<Duration>, log: &mut CommandLog) -> anyhow::Result<ExitStatus> {
+    log.note(&format!("running {:?}", cmd))?;
+    let mut written = Ok(());
+    let status = run_command(cmd, timeout, &mut |is_stdout, buffer| {
+        if written.is_ok() {
+            written = log.write(is_stdout, buffer);
+        }
+    });
+    written?;
+    log.finish()?;
+    match &status {
+        Ok(status) => log.note(&format!("exited with {}", status))?,
+        Err(err) => log.note(&format!("{:#}", err))?,
+    }
+    status
+}
+
+/// Spawns `cmd` and hands every chunk of its output to `output` as it
+/// arrives.
+fn run_command(cmd: &mut Command, timeout: Option<Duration>, output: &mut dyn FnMut(bool, &[u8])) -> anyhow::Result<ExitStatus> {
+    use anyhow::Context;
+    use crate::command::{diagnose::diagnose_spawn, interrupt, read2};
+    if interrupt::is_interrupted() {
+        return Err(CommandError::Interrupted { cmd: format!("{:?}", cmd) }.into());
+    }
+    #[cfg(unix)]
+    {
+        // A group of its own, so that the whole tree can be stopped at once.
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    let mut child = match cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            let dir = cmd.get_current_dir().map(Path::to_path_buf)
+                .unwrap_or_else(|| std::env::current_dir().unwrap_or_default());
+            return Err(CommandError::Spawn {
+                cmd: format!("{:?}", cmd),
+                reason: diagnose_spawn(cmd.get_program(), &dir, &err)
+            }.into());
+        }
+    };
+
+    let pid = child.id() as libc::pid_t;
+    interrupt::register(pid);
+
+    let (done, watchdog) = match timeout {
+        Some(timeout) => {
+            let (done, wait_done) = mpsc::channel::<()>();
+            let watchdog = thread::spawn(move || match wait_done.recv_timeout(timeout) {
+                Err(RecvTimeoutError::Timeout) => {
+                    interrupt::signal_group(pid, libc::SIGTERM);
+                    if let Err(RecvTimeoutError::Timeout) = wait_done.recv_timeout(KILL_GRACE) {
+                        interrupt::signal_group(pid, libc::SIGKILL);
+                    }
+                    true
+                }
+                _ => false,
+            });
+            (Some(done), Some(watchdog))
+        }
+        None => (None, None),
+    };
+
     let mut stdout_writer = std::io::LineWriter::new(std::io::stdout());
     let mut stderr_writer = std::io::LineWriter::new(std::io::stderr());
-    read2::read2(
+    let read = read2::read2(
         child.stdout.take().unwrap(),
         child.stderr.take().unwrap(),
         &mut |is_stdout, buffer, _is_done| {
@@ -21,41 +153,97 @@ fn run_command_with_output(cmd: &mut Command) -> anyhow::Result<process::Output>
             if log::log_enabled!(target: "raw_cargo_messages", log::Level::Trace) {
                 use std::io::Write;
                 if is_stdout {
-                    stdout_writer.write_all(&buffer[stdout.len()..]).unwrap();
+                    stdout_writer.write_all(buffer).unwrap();
                 } else {
-                    stderr_writer.write_all(&buffer[stderr.len()..]).unwrap();
+                    stderr_writer.write_all(buffer).unwrap();
                 }
             }
-            if is_stdout {
-                stdout = buffer.clone();
-            } else {
-                stderr = buffer.clone();
-            }
+            output(is_stdout, buffer);
+            buffer.clear();
         },
-    )?;
+    );
 
-    let status = child
-        .wait()
-        .with_context(|| "failed to wait on child process")?;
+    let status = child.wait();
+    interrupt::unregister(pid);
+    drop(done);
+    read?;
+    let status = status.with_context(|| "failed to wait on child process")?;
 
-    Ok(process::Output {
-        status,
-        stdout,
-        stderr,
-    })
+    if let Some(watchdog) = watchdog {
+        if watchdog.join().unwrap_or(false) {
+            return Err(CommandError::TimedOut {
+                cmd: format!("{:?}", cmd),
+                timeout: timeout.unwrap_or_default(),
+                output: String::new()
+            }.into());
+        }
+    }
+    if !status.success() && interrupt::is_interrupted() {
+        return Err(CommandError::Interrupted { cmd: format!("{:?}", cmd) }.into());
+    }
+
+    Ok(status)
 }
 
 pub fn command_output(cmd: &mut Command) -> anyhow::Result<process::Output> {
-    let output = run_command_with_output(cmd)?;
+    command_output_with_timeout(cmd, None)
+}
+
+pub fn command_output_with_timeout(cmd: &mut Command, timeout: Option<Duration>) -> anyhow::Result<process::Output> {
+    let output = run_command_with_timeout(cmd, timeout)?;
 
     if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "expected success, got {}\n\nstderr={}\n\n stdout={}\n",
-            output.status,
-            String::from_utf8_lossy(&output.stderr),
-            String::from_utf8_lossy(&output.stdout)
-        ));
+        return Err(CommandError::Failed {
+            status: output.status,
+            output: format_output(&output.stdout, &output.stderr)
+        }.into());
     }
 
     Ok(output)
-}
\ No newline at end of file
+}
+
+fn format_output(stdout: &[u8], stderr: &[u8]) -> String {
+    format!("stderr={}\n\n stdout={}\n", String::from_utf8_lossy(stderr), String::from_utf8_lossy(stdout))
+}
+
+/// Like `command_output_with_timeout`, but the output goes to `log` and only
+/// its last lines make it into the error.
+pub fn command_output_logged(cmd: &mut Command, timeout: Option<Duration>, log: &mut CommandLog) -> anyhow::Result<()> {
+    let status = match run_command_logged(cmd, timeout, log) {
+        Ok(status) => status,
+        Err(err) => return Err(match err.downcast::<CommandError>() {
+            Ok(CommandError::TimedOut { cmd, timeout, .. }) => CommandError::TimedOut {
+                cmd,
+                timeout,
+                output: format!("\nlog={}\n{}\n", log.path().display(), log.tail())
+            }.into(),
+            Ok(err) => err.into(),
+            Err(err) => err,
+        }),
+    };
+
+    if !status.success() {
+        return Err(CommandError::Failed {
+            status,
+            output: format!("log={}\n{}\n", log.path().display(), log.tail())
+        }.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timed_out_output() {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg("echo started; sleep 30");
+
+    let err = command_output_with_timeout(&mut cmd, Some(Duration::from_millis(500))).unwrap_err();
+    match CommandError::find(&err) {
+        Some(CommandError::TimedOut { timeout, output, .. }) => {
+            assert_eq!(*timeout, Duration::from_millis(500));
+            assert!(output.contains("stdout=started"), "{}", output);
+        }
+        _ => panic!("expected a timeout, got {:#}", err),
+    }
+}
@@ -0,0 +1,60 @@
+use std::{env, ffi::OsStr, fs::File, io::{self, BufRead, BufReader}, path::{Path, PathBuf}};
+
+/// Finds `program` in `PATH` the way spawning it would.
+pub fn find_in_path(program: &OsStr) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|path| is_executable(path))
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Explains why spawning `program` from `dir` failed with `err`, e.g. a
+/// missing executable bit or a shebang naming an interpreter that is not
+/// installed, which the OS reports as a bare "No such file or directory".
+pub fn diagnose_spawn(program: &OsStr, dir: &Path, err: &io::Error) -> String {
+    let path = if Path::new(program).components().count() > 1 {
+        dir.join(program)
+    } else {
+        match find_in_path(program) {
+            Some(path) => path,
+            None => return format!("{:?} is not found in PATH ({})", program, err),
+        }
+    };
+
+    if !path.exists() {
+        return format!("{:?} does not exist ({})", path, err);
+    }
+    if !is_executable(&path) {
+        return format!("{:?} is not executable; run it through its interpreter or `chmod +x` it ({})", path, err);
+    }
+    if let Some(interpreter) = shebang(&path) {
+        let found = match interpreter.as_slice() {
+            [env, name, ..] if env.ends_with("/env") => find_in_path(OsStr::new(name)).is_some(),
+            [interpreter, ..] => is_executable(Path::new(interpreter)),
+            [] => true,
+        };
+        if !found {
+            return format!("{:?} starts with `#!{}` but that interpreter is not installed ({})", path, interpreter.join(" "), err);
+        }
+    }
+    format!("fail to spawn {:?}: {}", path, err)
+}
+
+/// The interpreter and its arguments named on the `#!` line of `path`.
+fn shebang(path: &Path) -> Option<Vec<String>> {
+    let mut line = String::new();
+    BufReader::new(File::open(path).ok()?).read_line(&mut line).ok()?;
+    let line = line.strip_prefix("#!")?;
+    Some(line.split_whitespace().map(String::from).collect())
+}
@@ -0,0 +1,76 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, Mutex}, thread, time::Duration};
+
+/// How long running commands get to exit after the first SIGINT/SIGTERM is
+/// forwarded to them before they are killed.
+const GRACE: Duration = Duration::from_secs(30);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Process groups of the commands running right now.
+static GROUPS: Mutex<Vec<libc::pid_t>> = Mutex::new(Vec::new());
+
+/// Handles SIGINT and SIGTERM by forwarding them to the process group of
+/// every running command, killing what is left after a grace period.
+/// Afterwards `is_interrupted` is true and commands refuse to start, so
+/// callers wind down and record where they stopped. A second signal kills
+/// the running commands and exits right away.
+#[cfg(unix)]
+pub fn install() -> anyhow::Result<()> {
+    use signal_hook::{consts::{SIGINT, SIGTERM}, iterator::Signals};
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                eprintln!("interrupted again, killing running commands");
+                signal_groups(libc::SIGKILL);
+                std::process::exit(130);
+            }
+            eprintln!("interrupted, waiting up to {:?} for running commands to stop", GRACE);
+            signal_groups(signal);
+            thread::spawn(|| {
+                thread::sleep(GRACE);
+                signal_groups(libc::SIGKILL);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn install() -> anyhow::Result<()> {
+    Ok(())
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Tracks the process group of a command that was just spawned. Commands
+/// spawned while the signal was being handled are stopped right away.
+pub fn register(pgid: libc::pid_t) {
+    GROUPS.lock().unwrap().push(pgid);
+    if is_interrupted() {
+        signal_group(pgid, libc::SIGTERM);
+    }
+}
+
+pub fn unregister(pgid: libc::pid_t) {
+    GROUPS.lock().unwrap().retain(|group| *group != pgid);
+}
+
+fn signal_groups(signal: libc::c_int) {
+    for pgid in GROUPS.lock().unwrap().iter() {
+        signal_group(*pgid, signal);
+    }
+}
+
+#[cfg(unix)]
+pub fn signal_group(pgid: libc::pid_t, signal: libc::c_int) {
+    unsafe {
+        libc::killpg(pgid, signal);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn signal_group(_pgid: libc::pid_t, _signal: libc::c_int) {}
@@ -0,0 +1,101 @@
+use std::{collections::VecDeque, path::{Path, PathBuf}, fs::{File, create_dir_all}, io::{self, BufWriter, Write}};
+
+use chrono::Local;
+
+/// Output of one command, written line by line to a file with timestamps.
+/// Only the last `tail_len` lines are kept in memory, for error messages.
+pub struct CommandLog {
+    path: PathBuf,
+    file: BufWriter<File>,
+    // Unfinished last line of stdout and of stderr.
+    partial: [Vec<u8>; 2],
+    tail: VecDeque<String>,
+    tail_len: usize
+}
+
+impl CommandLog {
+    pub fn create(path: &Path, tail_len: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        Ok(CommandLog {
+            path: path.to_path_buf(),
+            file: BufWriter::new(File::create(path)?),
+            partial: [Vec::new(), Vec::new()],
+            tail: VecDeque::new(),
+            tail_len
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a line of our own, e.g. the command being run.
+    pub fn note(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.file, "{} [roller] {}", timestamp(), line)?;
+        self.file.flush()
+    }
+
+    /// Appends output of the command. Lines are written once complete.
+    pub fn write(&mut self, is_stdout: bool, bytes: &[u8]) -> io::Result<()> {
+        let stream = if is_stdout { 0 } else { 1 };
+        self.partial[stream].extend_from_slice(bytes);
+        while let Some(end) = self.partial[stream].iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial[stream].drain(..=end).collect();
+            self.line(is_stdout, &line[..end])?;
+        }
+        self.file.flush()
+    }
+
+    /// Writes what is left of lines without a trailing newline.
+    pub fn finish(&mut self) -> io::Result<()> {
+        for (stream, is_stdout) in [(0, true), (1, false)] {
+            if !self.partial[stream].is_empty() {
+                let line = std::mem::take(&mut self.partial[stream]);
+                self.line(is_stdout, &line)?;
+            }
+        }
+        self.file.flush()
+    }
+
+    /// The last lines of output, oldest first.
+    pub fn tail(&self) -> String {
+        self.tail.iter().map(String::as_str).collect::<Vec<_>>().join("\n")
+    }
+
+    fn line(&mut self, is_stdout: bool, line: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        let stream = if is_stdout { "stdout" } else { "stderr" };
+        writeln!(self.file, "{} [{}] {}", timestamp(), stream, line)?;
+
+        if self.tail_len > 0 {
+            if self.tail.len() == self.tail_len {
+                self.tail.pop_front();
+            }
+            self.tail.push_back(String::from(line));
+        }
+        Ok(())
+    }
+}
+
+fn timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+#[test]
+fn test_command_log_tail() {
+    let path = std::env::temp_dir().join(format!("commit_roller_log_{}.log", std::process::id()));
+    let mut log = CommandLog::create(&path, 2).unwrap();
+    log.write(true, b"one\ntw").unwrap();
+    log.write(false, b"error\n").unwrap();
+    log.write(true, b"o\nthree").unwrap();
+    log.finish().unwrap();
+    assert_eq!(log.tail(), "two\nthree");
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<_> = text.lines().map(|line| line.split_once(' ').unwrap().1.split_once(' ').unwrap().1).collect();
+    assert_eq!(lines, ["[stdout] one", "[stderr] error", "[stdout] two", "[stdout] three"]);
+    std::fs::remove_file(&path).unwrap();
+}
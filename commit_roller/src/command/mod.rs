@@ -1,3 +1,8 @@
 pub mod command_output;
+pub mod diagnose;
+pub mod interrupt;
+pub mod log;
+pub mod perf;
 pub mod read2;
+pub mod rusage;
 pub mod cli;
\ No newline at end of file
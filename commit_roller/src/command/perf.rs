@@ -0,0 +1,131 @@
+use std::{fmt, io};
+
+/// An event counted by `Counters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Instructions,
+    Cycles,
+    BranchMisses,
+    // In nanoseconds.
+    TaskClock
+}
+
+const EVENTS: [Event; 4] = [Event::Instructions, Event::Cycles, Event::BranchMisses, Event::TaskClock];
+
+/// What `Counters` counted, `None` for events the kernel refused or never got
+/// to count.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Counts {
+    pub instructions: Option<u64>,
+    pub cycles: Option<u64>,
+    pub branch_misses: Option<u64>,
+    pub task_clock_ns: Option<u64>
+}
+
+/// Counters of `EVENTS` for the commands the calling thread spawns after
+/// opening them, and the children of those commands. They start counting when
+/// a spawned command execs, so the thread itself is not counted, and only
+/// count user space so that they work with `perf_event_paranoid` up to 2.
+pub struct Counters {
+    #[cfg(target_os = "linux")]
+    fds: Vec<(Event, std::fs::File)>,
+    // Events that could not be opened, and why.
+    pub unavailable: Vec<(Event, io::Error)>
+}
+
+impl Counters {
+    #[cfg(target_os = "linux")]
+    pub fn open() -> Self {
+        use std::os::fd::FromRawFd;
+        use perf_event_open_sys::{bindings, perf_event_open};
+
+        let mut counters = Counters { fds: Vec::new(), unavailable: Vec::new() };
+        for event in EVENTS {
+            let (type_, config) = match event {
+                Event::Instructions => (bindings::perf_type_id_PERF_TYPE_HARDWARE, bindings::perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS),
+                Event::Cycles => (bindings::perf_type_id_PERF_TYPE_HARDWARE, bindings::perf_hw_id_PERF_COUNT_HW_CPU_CYCLES),
+                Event::BranchMisses => (bindings::perf_type_id_PERF_TYPE_HARDWARE, bindings::perf_hw_id_PERF_COUNT_HW_BRANCH_MISSES),
+                Event::TaskClock => (bindings::perf_type_id_PERF_TYPE_SOFTWARE, bindings::perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK),
+            };
+            let mut attr = bindings::perf_event_attr {
+                size: std::mem::size_of::<bindings::perf_event_attr>() as u32,
+                type_,
+                config: config as u64,
+                read_format: (bindings::perf_event_read_format_PERF_FORMAT_TOTAL_TIME_ENABLED
+                    | bindings::perf_event_read_format_PERF_FORMAT_TOTAL_TIME_RUNNING) as u64,
+                ..Default::default()
+            };
+            attr.set_disabled(1);
+            attr.set_inherit(1);
+            attr.set_enable_on_exec(1);
+            attr.set_exclude_kernel(1);
+            attr.set_exclude_hv(1);
+
+            let fd = unsafe { perf_event_open(&mut attr, 0, -1, -1, bindings::PERF_FLAG_FD_CLOEXEC as libc::c_ulong) };
+            if fd == -1 {
+                counters.unavailable.push((event, io::Error::last_os_error()));
+            } else {
+                counters.fds.push((event, unsafe { std::fs::File::from_raw_fd(fd) }));
+            }
+        }
+        counters
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open() -> Self {
+        let unavailable = EVENTS.into_iter()
+            .map(|event| (event, io::Error::new(io::ErrorKind::Unsupported, "perf_event_open is only available on Linux")))
+            .collect();
+        Counters { unavailable }
+    }
+
+    /// Reads the counters once every spawned command has been waited for,
+    /// scaled up for the time the kernel multiplexed them out.
+    #[cfg(target_os = "linux")]
+    pub fn read(&self) -> Counts {
+        use std::io::Read;
+
+        let mut counts = Counts::default();
+        for (event, fd) in &self.fds {
+            let mut buf = [0u8; 24];
+            if (&*fd).read_exact(&mut buf).is_err() {
+                continue;
+            }
+            let [value, enabled, running] = [0, 8, 16].map(|at| u64::from_ne_bytes(buf[at..at + 8].try_into().unwrap()));
+            // Never scheduled onto the PMU, so nothing was counted at all.
+            if running == 0 {
+                continue;
+            }
+            let value = if running == enabled {
+                value
+            } else {
+                (value as f64 * enabled as f64 / running as f64) as u64
+            };
+            let slot = match event {
+                Event::Instructions => &mut counts.instructions,
+                Event::Cycles => &mut counts.cycles,
+                Event::BranchMisses => &mut counts.branch_misses,
+                Event::TaskClock => &mut counts.task_clock_ns,
+            };
+            *slot = Some(value);
+        }
+        counts
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn read(&self) -> Counts {
+        Counts::default()
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Event::Instructions => "instructions",
+            Event::Cycles => "cycles",
+            Event::BranchMisses => "branch-misses",
+            Event::TaskClock => "task-clock",
+        };
+        f.write_str(s)
+    }
+}
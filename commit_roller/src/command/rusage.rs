@@ -0,0 +1,66 @@
+use std::{process::{Command, ExitStatus}, time::{Duration, Instant}};
+
+use super::{command_output::CommandError, interrupt};
+
+/// Resources used by a command and every process it waited for.
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub status: ExitStatus,
+    pub wall: Duration,
+    pub user: Duration,
+    pub sys: Duration,
+    // In kilobytes, of the largest single process.
+    pub max_rss: u64
+}
+
+/// Runs `cmd` to its end, reaping it with `wait4` to get its resource usage.
+/// Output goes wherever `cmd` sends it.
+#[cfg(unix)]
+pub fn run_measured(cmd: &mut Command) -> anyhow::Result<Usage> {
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+
+    if interrupt::is_interrupted() {
+        return Err(CommandError::Interrupted { cmd: format!("{:?}", cmd) }.into());
+    }
+    cmd.process_group(0);
+
+    let start = Instant::now();
+    let child = cmd.spawn()
+        .map_err(|err| anyhow::anyhow!("failed to spawn process for cmd: {:?}\n{}", cmd, err))?;
+    let pid = child.id() as libc::pid_t;
+    interrupt::register(pid);
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let waited = loop {
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) };
+        if ret != -1 {
+            break Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            break Err(err);
+        }
+    };
+    let wall = start.elapsed();
+    interrupt::unregister(pid);
+    waited.map_err(|err| anyhow::anyhow!("failed to wait on child process {:?}\n{}", cmd, err))?;
+
+    let status = ExitStatus::from_raw(status);
+    if !status.success() && interrupt::is_interrupted() {
+        return Err(CommandError::Interrupted { cmd: format!("{:?}", cmd) }.into());
+    }
+
+    Ok(Usage {
+        status,
+        wall,
+        user: timeval(rusage.ru_utime),
+        sys: timeval(rusage.ru_stime),
+        max_rss: rusage.ru_maxrss as u64
+    })
+}
+
+#[cfg(unix)]
+fn timeval(tv: libc::timeval) -> Duration {
+    Duration::from_secs(tv.tv_sec as u64) + Duration::from_micros(tv.tv_usec as u64)
+}
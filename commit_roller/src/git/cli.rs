@@ -0,0 +1,107 @@
+use std::{path::{Path, PathBuf}, process::Command};
+
+use crate::command::command_output::{command_output_with_timeout, run_command_with_timeout};
+
+use super::{command, CommitInfo, GitBackend, LogQuery, GIT_TIMEOUT};
+
+const DATE_FMT: &str = "%Y-%m-%d";
+const LOG_FORMAT: &str = "--format=%H%x09%P%x09%an%x09%ad%x09%s";
+
+pub struct CliBackend {
+    repo_dir: PathBuf
+}
+
+impl CliBackend {
+    pub fn new(repo_dir: &Path) -> Self {
+        CliBackend { repo_dir: repo_dir.to_path_buf() }
+    }
+
+    fn git(&self) -> Command {
+        let mut cmd = command();
+        cmd.current_dir(&self.repo_dir);
+        cmd
+    }
+
+    fn stdout(&self, cmd: &mut Command) -> anyhow::Result<String> {
+        let output = command_output_with_timeout(cmd, Some(GIT_TIMEOUT))?;
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl GitBackend for CliBackend {
+    fn log(&self, query: &LogQuery) -> anyhow::Result<Vec<CommitInfo>> {
+        let mut cmd = self.git();
+        cmd.arg("log").arg(LOG_FORMAT).arg("--date=short");
+        if query.first_parent {
+            cmd.arg("--first-parent");
+        }
+        if let Some(after) = query.after {
+            cmd.arg(format!("--after={}", after.format(DATE_FMT)));
+        }
+        if let Some(before) = query.before {
+            cmd.arg(format!("--before={}", before.format(DATE_FMT)));
+        }
+
+        Ok(self.stdout(&mut cmd)?.lines().filter_map(parse_commit).collect())
+    }
+
+    fn commit(&self, rev: &str) -> anyhow::Result<CommitInfo> {
+        let mut cmd = self.git();
+        cmd.arg("log").arg(LOG_FORMAT).arg("--date=short").arg("-n").arg("1").arg(rev);
+
+        self.stdout(&mut cmd)?.lines().find_map(parse_commit)
+            .ok_or_else(|| anyhow::anyhow!("Fail to find commit {}", rev))
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> anyhow::Result<bool> {
+        let mut cmd = self.git();
+        cmd.arg("merge-base").arg("--is-ancestor").arg(ancestor).arg(descendant);
+
+        // `--is-ancestor` exits with 1 for "no" and anything else on errors.
+        let output = run_command_with_timeout(&mut cmd, Some(GIT_TIMEOUT))?;
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(anyhow::anyhow!(
+                "Fail to check whether {} is an ancestor of {}\n{}",
+                ancestor, descendant, String::from_utf8_lossy(&output.stderr)
+            )),
+        }
+    }
+
+    fn range(&self, base: &str, tip: &str) -> anyhow::Result<Vec<String>> {
+        let mut cmd = self.git();
+        cmd.arg("rev-list").arg("--reverse").arg("--topo-order").arg(format!("{}..{}", base, tip));
+
+        Ok(self.stdout(&mut cmd)?.lines().map(String::from).collect())
+    }
+
+    fn count(&self, base: &str, tip: &str) -> anyhow::Result<u64> {
+        let mut cmd = self.git();
+        cmd.arg("rev-list").arg("--count").arg(format!("{}..{}", base, tip));
+
+        let stdout = self.stdout(&mut cmd)?;
+        stdout.trim().parse()
+            .map_err(|err| anyhow::anyhow!("Fail to parse commit count {:?}\n{}", stdout, err))
+    }
+
+    fn checkout(&self, hash: &str) -> anyhow::Result<()> {
+        let mut cmd = self.git();
+        cmd.arg("checkout").arg(hash);
+
+        command_output_with_timeout(&mut cmd, Some(GIT_TIMEOUT))?;
+        Ok(())
+    }
+}
+
+/// Parses a line of `LOG_FORMAT`.
+fn parse_commit(line: &str) -> Option<CommitInfo> {
+    let mut fields = line.splitn(5, '\t');
+    Some(CommitInfo {
+        hash: String::from(fields.next()?),
+        parents: fields.next()?.split_whitespace().map(String::from).collect(),
+        author: String::from(fields.next()?),
+        date: String::from(fields.next()?),
+        subject: String::from(fields.next()?)
+    })
+}
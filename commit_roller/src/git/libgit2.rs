@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use chrono::{FixedOffset, Local, NaiveDate, TimeZone};
+use git2::{build::CheckoutBuilder, Commit, Oid, Repository, Sort};
+
+use super::{CommitInfo, GitBackend, LogQuery};
+
+/// How far past `LogQuery::after` the walk goes on. Committer dates are not
+/// monotonic along the history, so the first commit older than `after` may be
+/// followed by newer ones.
+const CLOCK_SKEW_SECS: i64 = 24 * 60 * 60;
+
+pub struct Libgit2Backend {
+    repo: Repository
+}
+
+impl Libgit2Backend {
+    pub fn open(repo_dir: &Path) -> anyhow::Result<Self> {
+        Ok(Libgit2Backend { repo: Repository::open(repo_dir)? })
+    }
+
+    fn oid(&self, rev: &str) -> anyhow::Result<Oid> {
+        Ok(self.repo.revparse_single(rev)?.peel_to_commit()?.id())
+    }
+}
+
+impl GitBackend for Libgit2Backend {
+    fn log(&self, query: &LogQuery) -> anyhow::Result<Vec<CommitInfo>> {
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TIME)?;
+        if query.first_parent {
+            walk.simplify_first_parent()?;
+        }
+        walk.push_head()?;
+
+        let after = query.after.map(timestamp);
+        let before = query.before.map(timestamp);
+
+        let mut commits = vec![];
+        for oid in walk {
+            let commit = self.repo.find_commit(oid?)?;
+            let time = commit.time().seconds();
+            if before.is_some_and(|before| time > before) {
+                continue;
+            }
+            if let Some(after) = after {
+                // Walking by time, so everything from here on is older,
+                // give or take the skew.
+                if time < after - CLOCK_SKEW_SECS {
+                    break;
+                }
+                if time < after {
+                    continue;
+                }
+            }
+            commits.push(commit_info(&commit));
+        }
+        Ok(commits)
+    }
+
+    fn commit(&self, rev: &str) -> anyhow::Result<CommitInfo> {
+        let commit = self.repo.find_commit(self.oid(rev)?)?;
+        Ok(commit_info(&commit))
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> anyhow::Result<bool> {
+        let (ancestor, descendant) = (self.oid(ancestor)?, self.oid(descendant)?);
+        Ok(ancestor == descendant || self.repo.graph_descendant_of(descendant, ancestor)?)
+    }
+
+    fn range(&self, base: &str, tip: &str) -> anyhow::Result<Vec<String>> {
+        let mut walk = self.repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        walk.push(self.oid(tip)?)?;
+        walk.hide(self.oid(base)?)?;
+
+        walk.map(|oid| Ok(oid?.to_string())).collect()
+    }
+
+    fn count(&self, base: &str, tip: &str) -> anyhow::Result<u64> {
+        let mut walk = self.repo.revwalk()?;
+        walk.push(self.oid(tip)?)?;
+        walk.hide(self.oid(base)?)?;
+
+        let mut count = 0;
+        for oid in walk {
+            oid?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn checkout(&self, hash: &str) -> anyhow::Result<()> {
+        let oid = self.oid(hash)?;
+        let commit = self.repo.find_commit(oid)?;
+        // Refuses to overwrite local modifications, like `git checkout`.
+        self.repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        self.repo.set_head_detached(oid)?;
+        Ok(())
+    }
+}
+
+/// The timestamp git resolves a date without a time to, which is that day at
+/// the current local time of day.
+fn timestamp(date: NaiveDate) -> i64 {
+    let now = Local::now();
+    Local.from_local_datetime(&date.and_time(now.time()))
+        .earliest()
+        .unwrap_or(now)
+        .timestamp()
+}
+
+fn commit_info(commit: &Commit) -> CommitInfo {
+    let author = commit.author();
+    let time = author.when();
+    let date = FixedOffset::east_opt(time.offset_minutes() * 60)
+        .and_then(|offset| offset.timestamp_opt(time.seconds(), 0).single())
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default();
+
+    CommitInfo {
+        hash: commit.id().to_string(),
+        parents: commit.parent_ids().map(|oid| oid.to_string()).collect(),
+        author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+        date,
+        subject: String::from_utf8_lossy(commit.summary_bytes().unwrap_or_default()).into_owned()
+    }
+}
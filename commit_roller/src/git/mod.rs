@@ -0,0 +1,162 @@
+use std::{path::Path, process::Command, time::Duration};
+
+use chrono::NaiveDate;
+
+pub mod cli;
+pub mod libgit2;
+
+/// Longest a single `git` command may take. Cloning rust-lang/rust takes
+/// minutes; waiting on a credential prompt would take forever.
+pub const GIT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// A `git` command that fails instead of prompting for credentials.
+pub fn command() -> Command {
+    let mut cmd = Command::new("git");
+    cmd.env("GIT_TERMINAL_PROMPT", "0").env("GCM_INTERACTIVE", "never");
+    cmd
+}
+
+/// Which implementation of `GitBackend` to use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    // Spawns the `git` executable and parses its output.
+    Cli,
+    // Reads the repository in-process through libgit2.
+    Libgit2
+}
+
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    pub hash: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    // Author date as `YYYY-MM-DD`.
+    pub date: String,
+    pub subject: String
+}
+
+/// Which commits reachable from HEAD `GitBackend::log` walks.
+#[derive(Debug, Default)]
+pub struct LogQuery {
+    pub first_parent: bool,
+    // Bounds on the committer date. Like `git log --after=YYYY-MM-DD`, a
+    // date stands for that day at the current local time of day.
+    pub after: Option<NaiveDate>,
+    pub before: Option<NaiveDate>
+}
+
+/// Every git operation needed to find and roll back commits of a repository.
+pub trait GitBackend {
+    /// Commits matching `query`, newest first.
+    fn log(&self, query: &LogQuery) -> anyhow::Result<Vec<CommitInfo>>;
+
+    /// Looks up the commit a revision resolves to.
+    fn commit(&self, rev: &str) -> anyhow::Result<CommitInfo>;
+
+    fn first_parent(&self, hash: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.commit(hash)?.parents.into_iter().next())
+    }
+
+    fn is_ancestor(&self, ancestor: &str, descendant: &str) -> anyhow::Result<bool>;
+
+    /// Commits reachable from `tip` but not from `base`, oldest first.
+    fn range(&self, base: &str, tip: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Number of commits `range` would return.
+    fn count(&self, base: &str, tip: &str) -> anyhow::Result<u64>;
+
+    /// Detaches HEAD of the working tree at `hash`.
+    fn checkout(&self, hash: &str) -> anyhow::Result<()>;
+}
+
+pub fn open(kind: BackendKind, repo_dir: &Path) -> anyhow::Result<Box<dyn GitBackend>> {
+    match kind {
+        BackendKind::Cli => Ok(Box::new(cli::CliBackend::new(repo_dir))),
+        BackendKind::Libgit2 => Ok(Box::new(libgit2::Libgit2Backend::open(repo_dir)?)),
+    }
+}
+
+/// Builds a repository at `repo_dir` shaped like a bors merge, where `init`
+/// adds `lib.rs` and `Treat macros as HIR items` changes it:
+///
+/// ```text
+/// *   Auto merge of #87234 (2021-11-03)
+/// |\
+/// | * Update tests (2021-11-02)
+/// | * Treat macros as HIR items (2021-11-01)
+/// * | Unrelated (2021-11-02)
+/// |/
+/// * init (2021-10-30)
+/// ```
+#[cfg(test)]
+pub fn fixture_repo(repo_dir: &Path) {
+    let git = |args: &[&str], date: &str| {
+        let output = command().current_dir(repo_dir)
+            .args(["-c", "user.name=roller", "-c", "user.email=roller@example.com"])
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {:?}\n{}", args, String::from_utf8_lossy(&output.stderr));
+    };
+    let _ = std::fs::remove_dir_all(repo_dir);
+    std::fs::create_dir_all(repo_dir).unwrap();
+
+    git(&["init", "-q", "-b", "master"], "2021-10-30T12:00:00");
+    std::fs::write(repo_dir.join("lib.rs"), "fn a() {}\n").unwrap();
+    git(&["add", "lib.rs"], "2021-10-30T12:00:00");
+    git(&["commit", "-q", "-m", "init"], "2021-10-30T12:00:00");
+    git(&["checkout", "-q", "-b", "pr"], "2021-11-01T12:00:00");
+    std::fs::write(repo_dir.join("lib.rs"), "fn b() {}\n").unwrap();
+    git(&["commit", "-q", "-a", "-m", "Treat macros as HIR items"], "2021-11-01T12:00:00");
+    git(&["commit", "-q", "--allow-empty", "-m", "Update tests"], "2021-11-02T12:00:00");
+    git(&["checkout", "-q", "master"], "2021-11-02T12:00:00");
+    git(&["commit", "-q", "--allow-empty", "-m", "Unrelated"], "2021-11-02T13:00:00");
+    git(&["merge", "-q", "--no-ff", "pr", "-m", "Auto merge of #87234 - cjgillot:macro-hir, r=petrochenkov"], "2021-11-03T12:00:00");
+}
+
+#[test]
+fn test_backends_agree() {
+    let repo_dir = std::env::temp_dir().join(format!("commit_roller_backends_{}", std::process::id()));
+    fixture_repo(&repo_dir);
+    let (cli, libgit2) = (open(BackendKind::Cli, &repo_dir).unwrap(), open(BackendKind::Libgit2, &repo_dir).unwrap());
+
+    let hashes = |commits: Vec<CommitInfo>| commits.into_iter().map(|commit| commit.hash).collect::<Vec<_>>();
+    let queries = [
+        LogQuery::default(),
+        LogQuery { first_parent: true, ..LogQuery::default() },
+        LogQuery { after: NaiveDate::from_ymd_opt(2021, 11, 1), before: NaiveDate::from_ymd_opt(2021, 11, 3), ..LogQuery::default() }
+    ];
+    for query in &queries {
+        assert_eq!(hashes(cli.log(query).unwrap()), hashes(libgit2.log(query).unwrap()), "{:?}", query);
+    }
+
+    let all = hashes(cli.log(&LogQuery::default()).unwrap());
+    assert_eq!(all.len(), 5);
+    for base in &all {
+        for tip in &all {
+            let range = cli.range(base, tip).unwrap();
+            assert_eq!(range, libgit2.range(base, tip).unwrap());
+            assert_eq!(cli.count(base, tip).unwrap(), range.len() as u64);
+            assert_eq!(libgit2.count(base, tip).unwrap(), range.len() as u64);
+            assert_eq!(cli.is_ancestor(base, tip).unwrap(), libgit2.is_ancestor(base, tip).unwrap());
+        }
+        let (a, b) = (cli.commit(base).unwrap(), libgit2.commit(base).unwrap());
+        assert_eq!((a.parents, a.author, a.date, a.subject), (b.parents, b.author, b.date, b.subject));
+    }
+
+    // Both check out clean trees and refuse to overwrite local modifications.
+    let (init, merge) = (&all[all.len() - 1], &all[0]);
+    let lib = repo_dir.join("lib.rs");
+    libgit2.checkout(init).unwrap();
+    assert_eq!(std::fs::read_to_string(&lib).unwrap(), "fn a() {}\n");
+    cli.checkout(merge).unwrap();
+    assert_eq!(std::fs::read_to_string(&lib).unwrap(), "fn b() {}\n");
+    std::fs::write(&lib, "fn local() {}\n").unwrap();
+    assert!(cli.checkout(init).is_err());
+    assert!(libgit2.checkout(init).is_err());
+    assert_eq!(std::fs::read_to_string(&lib).unwrap(), "fn local() {}\n");
+
+    std::fs::remove_dir_all(&repo_dir).unwrap();
+}
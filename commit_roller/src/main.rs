@@ -1,8 +1,14 @@
+use std::path::Path;
+
+use bench::{codegen::{diff_codegen, CodegenOptions}, compare::{compare, CompareOptions}, runner::{bench, BenchOptions}, size::{size_report, SizeOptions}};
 use clap::Parser;
-use command::cli::{Cli, self};
-use roller::{gitlog::find_commits, stash::stash_all};
+use command::{cli::{Cli, self}, interrupt};
+use roller::{bisect::bisect, config::ConfigTemplate, gitlog::find_commits, recipe::BuildRecipe, scheduler::Budget, stash::{stash_all, StashOptions}};
 
+mod bench;
 mod command;
+mod git;
+mod report;
 mod roller;
 
 fn main() {
@@ -11,16 +17,155 @@ fn main() {
         cli::Commands::FindCommit { 
             repo_dir, 
             commits_json, 
-            out 
+            out,
+            max_distance,
+            git_backend
         } => {
-            find_commits(&repo_dir, &commits_json, &out);
+            if !find_commits(&repo_dir, &commits_json, &out, max_distance, git_backend) {
+                std::process::exit(1);
+            }
         },
         cli::Commands::RollBack { 
             repo_dir, 
             commit_context_json, 
             out_dir,
+            jobs,
+            min_free_gb,
+            job_disk_gb,
+            resume,
+            build
+        } => {
+            handle_interrupts();
+            let budget = Budget::new(jobs, build.build_jobs, min_free_gb << 30, job_disk_gb << 30);
+            let options = stash_options(build, &out_dir, budget, resume);
+            stash_all(&repo_dir, &out_dir, &commit_context_json, &options);
+        },
+        cli::Commands::Bisect {
+            repo_dir,
+            commit_context_json,
+            title,
+            out_dir,
+            predicate,
+            build
+        } => {
+            // Toolchains are built one at a time, so each gets every CPU, and
+            // whatever an earlier bisection built is picked up again.
+            handle_interrupts();
+            let budget = Budget::new(1, build.build_jobs, 0, 0);
+            let options = stash_options(build, &out_dir, budget, true);
+            if !bisect(&repo_dir, &out_dir, &commit_context_json, title.as_deref(), &predicate, &options) {
+                std::process::exit(1);
+            }
+        },
+        cli::Commands::Bench {
+            commit_context_json,
+            install_root,
+            corpus,
+            out_dir,
+            runs,
+            modes,
+            title,
+            self_profile,
+            summarize,
+            perf_counters
+        } => {
+            handle_interrupts();
+            let options = BenchOptions { runs, modes, title, self_profile, summarize, perf_counters };
+            if !bench(&commit_context_json, &install_root, &corpus, &out_dir, &options) {
+                std::process::exit(1);
+            }
+        },
+        cli::Commands::DiffCodegen {
+            commit_context_json,
+            install_root,
+            input,
+            out_dir,
+            emit,
+            opt_level,
+            crate_type,
+            edition,
+            rustc_args,
+            title
+        } => {
+            handle_interrupts();
+            let options = CodegenOptions { emits: emit, opt_level, crate_type, edition, rustc_args, title };
+            if !diff_codegen(&commit_context_json, &install_root, &input, &out_dir, &options) {
+                std::process::exit(1);
+            }
+        },
+        cli::Commands::SizeReport {
+            commit_context_json,
+            install_root,
+            corpus,
+            out_dir,
+            modes,
+            threshold_pct,
+            title
+        } => {
+            handle_interrupts();
+            let options = SizeOptions { modes, threshold_pct, title };
+            if !size_report(&commit_context_json, &install_root, &corpus, &out_dir, &options) {
+                std::process::exit(1);
+            }
+        },
+        cli::Commands::Compare {
+            results,
+            out,
+            metric,
+            alpha,
+            threshold_pct,
+            title
         } => {
-            stash_all(&repo_dir, &out_dir, &commit_context_json);
+            let out = out.unwrap_or_else(|| results.with_file_name("compare.json"));
+            let options = CompareOptions { metric, alpha, threshold_pct, title };
+            if !compare(&results, &out, &options) {
+                std::process::exit(1);
+            }
+        },
+    }
+
+    if interrupt::is_interrupted() {
+        std::process::exit(130);
+    }
+}
+
+/// Lets the subcommands that run builds and compilations wind down on
+/// SIGINT/SIGTERM, see `interrupt::install`. The others keep the default
+/// handling and stop right away.
+fn handle_interrupts() {
+    if let Err(err) = interrupt::install() {
+        eprintln!("warning: fail to handle SIGINT/SIGTERM\n{}", err);
+    }
+}
+
+fn stash_options(build: cli::BuildArgs, out_dir: &Path, budget: Budget, resume: bool) -> StashOptions {
+    let config_template = match ConfigTemplate::load(build.config_template.as_deref()) {
+        Ok(config_template) => config_template,
+        Err(err) => {
+            eprintln!("Fail to load config template\n{}", err);
+            std::process::exit(1);
+        }
+    };
+    let recipe = match BuildRecipe::load(&build.recipe) {
+        Ok(recipe) => BuildRecipe {
+            python: build.python.or(recipe.python),
+            timeout: build.step_timeout.or(recipe.timeout),
+            ..recipe
         },
+        Err(err) => {
+            eprintln!("Fail to load build recipe\n{}", err);
+            std::process::exit(1);
+        }
+    };
+    StashOptions {
+        backend: build.git_backend,
+        workspace: build.workspace,
+        config_template,
+        install_root: build.install_root.unwrap_or_else(|| out_dir.join("targets")),
+        recipe,
+        budget,
+        log_tail: build.log_tail,
+        clean_interrupted: build.clean_interrupted,
+        resume
     }
 }
@@ -0,0 +1,52 @@
+use std::{path::Path, fs::File, io::{BufWriter, Write}};
+
+use serde::Serialize;
+
+/// Writes `value` to `path` as pretty printed JSON.
+pub fn write_json(path: &Path, value: &impl Serialize) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Prints `rows` under `header` in left aligned columns, for pasting into
+/// notes.
+pub fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    for line in table(header, rows) {
+        println!("{}", line);
+    }
+}
+
+fn table<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> Vec<String> {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: [&str; N]| {
+        let line = cells.iter().zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        String::from(line.trim_end())
+    };
+    std::iter::once(line(header))
+        .chain(rows.iter().map(|row| line(row.each_ref().map(String::as_str))))
+        .collect()
+}
+
+#[test]
+fn test_table() {
+    let rows = [
+        [String::from("Treat macros"), String::from("cur"), String::new()],
+        [String::from("t"), String::from("old"), String::from("timed_out")],
+    ];
+    assert_eq!(table(["title", "side", "error"], &rows), [
+        "title         side  error",
+        "Treat macros  cur",
+        "t             old   timed_out",
+    ]);
+}
@@ -0,0 +1,241 @@
+use std::{collections::HashSet, ffi::OsString, path::Path, process::Command};
+
+use serde::Serialize;
+
+use crate::{command::{command_output::run_command_logged, interrupt, log::CommandLog}, git, report};
+
+use super::{gitlog::{short_hash, LogContext}, stash::{parse_commit_context_json, Rollback, Side, StashOptions}};
+
+/// What the predicate says about the toolchain of a commit.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Old,
+    New,
+    // The commit can't be tested, e.g. it doesn't build.
+    Skip
+}
+
+#[derive(Serialize, Debug)]
+struct Step {
+    hash: String,
+    subject: String,
+    verdict: Verdict,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reason: Option<String>
+}
+
+/// Written to `<out_dir>/bisect.json`.
+#[derive(Serialize, Debug)]
+struct BisectReport {
+    title: String,
+    hash_old: String,
+    hash_cur: String,
+    commits: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    first_new: Option<String>,
+    // When skipped commits leave more than one commit that could be first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    candidates: Vec<String>,
+    steps: Vec<Step>
+}
+
+/// Bisects the commits between `hash_old` (of the old behavior) and
+/// `hash_cur` (of the new one) of a context in `commit_context_json`,
+/// building the toolchain of each tested commit like `roll_back` does and
+/// running `predicate` with its install prefix. Returns whether the first
+/// commit of the new behavior was found.
+pub fn bisect(repo_dir: &Path, out_dir: &Path, commit_context_json: &Path, title: Option<&str>, predicate: &Path, options: &StashOptions) -> bool {
+    let context = match pick_context(commit_context_json, title) {
+        Ok(context) => context,
+        Err(err) => {
+            eprintln!("Fail to pick context from {:?}\n{}", commit_context_json, err);
+            return false;
+        }
+    };
+
+    let commits = match git::open(options.backend, repo_dir)
+        .and_then(|git| git.range(&context.hash_old, &context.hash_cur)?
+            .into_iter()
+            .map(|hash| git.commit(&hash))
+            .collect::<anyhow::Result<Vec<_>>>())
+    {
+        Ok(commits) if !commits.is_empty() => commits,
+        Ok(_) => {
+            eprintln!("Fail to bisect {:?}: {} is not a descendant of {}", context.title, short_hash(&context.hash_cur), short_hash(&context.hash_old));
+            return false;
+        }
+        Err(err) => {
+            eprintln!("Fail to list commits of {:?}\n{}", context.title, err);
+            return false;
+        }
+    };
+    println!("bisect {} commits of {:?} between {} and {}", commits.len(), context.title, short_hash(&context.hash_old), short_hash(&context.hash_cur));
+
+    let run = match Rollback::new(repo_dir, out_dir, options) {
+        Ok(run) => run,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        }
+    };
+
+    let mut steps = Vec::new();
+    let result = search(commits.len(), |index| {
+        let commit = &commits[index];
+        println!("testing {} {}", short_hash(&commit.hash), commit.subject);
+
+        let (verdict, reason) = test(&run, &context, &commit.hash, predicate)?;
+        println!("{} is {:?}", short_hash(&commit.hash), verdict);
+        steps.push(Step { hash: commit.hash.clone(), subject: commit.subject.clone(), verdict, reason });
+        Ok(verdict)
+    });
+    let result = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Fail to bisect {:?}, {} left as it was\n{:#}", context.title, out_dir.join("bisect.json").display(), err);
+            return false;
+        }
+    };
+
+    let mut report = BisectReport {
+        title: context.title.clone(),
+        hash_old: context.hash_old.clone(),
+        hash_cur: context.hash_cur.clone(),
+        commits: commits.len(),
+        first_new: None,
+        candidates: vec![],
+        steps
+    };
+    match &result {
+        Ok(index) => {
+            let commit = &commits[*index];
+            println!("first commit of the new behavior: {} {} ({}, {})", commit.hash, commit.subject, commit.author, commit.date);
+            report.first_new = Some(commit.hash.clone());
+        }
+        Err(indices) => {
+            println!("skipped commits leave {} candidates for the first commit of the new behavior:", indices.len());
+            for index in indices {
+                println!("{} {}", short_hash(&commits[*index].hash), commits[*index].subject);
+            }
+            report.candidates = indices.iter().map(|index| commits[*index].hash.clone()).collect();
+        }
+    }
+
+    if let Err(err) = write_report(out_dir, &report) {
+        eprintln!("Fail to write bisect report to {:?}\n{}", out_dir, err);
+    }
+    result.is_ok()
+}
+
+fn pick_context(commit_context_json: &Path, title: Option<&str>) -> anyhow::Result<LogContext> {
+    let contexts = parse_commit_context_json(commit_context_json)?;
+    let mut matching: Vec<_> = contexts.into_iter()
+        .filter(|context| title.is_none_or(|title| context.title == title))
+        .collect();
+    match matching.len() {
+        1 => Ok(matching.remove(0)),
+        0 => Err(anyhow::anyhow!("no context titled {:?}", title.unwrap_or_default())),
+        n => Err(anyhow::anyhow!("{} contexts to choose from, pick one with --title", n)),
+    }
+}
+
+/// Builds the toolchain of `hash` and runs `predicate` with its prefix. Like
+/// `git bisect run`, a commit that doesn't build or whose predicate exits
+/// with 125 is skipped, with the reason for a failed build, while exit codes
+/// above 127 and interrupts stop the bisection.
+fn test(run: &Rollback, context: &LogContext, hash: &str, predicate: &Path) -> anyhow::Result<(Verdict, Option<String>)> {
+    let context = LogContext::new(&context.title, hash, &context.hash_old);
+    let summary = run.stash(&context, Side::Cur);
+    if interrupt::is_interrupted() {
+        return Err(anyhow::anyhow!("interrupted while building {}", short_hash(hash)));
+    }
+    if !summary.is_installed() {
+        return Ok((Verdict::Skip, Some(format!("no toolchain: {}", summary.message.unwrap_or_default()))));
+    }
+
+    let mut path = OsString::from(summary.prefix.join("bin"));
+    if let Some(system) = std::env::var_os("PATH") {
+        path.push(":");
+        path.push(system);
+    }
+    let mut cmd = Command::new(predicate);
+    cmd.arg(&summary.prefix)
+        .env("ROLLER_PREFIX", &summary.prefix)
+        .env("ROLLER_HASH", hash)
+        .env("PATH", path);
+
+    let mut log = CommandLog::create(&summary.logs.join("predicate.log"), 0)?;
+    let status = run_command_logged(&mut cmd, None, &mut log)?;
+    if interrupt::is_interrupted() {
+        return Err(anyhow::anyhow!("interrupted while testing {}", short_hash(hash)));
+    }
+    match status.code() {
+        Some(0) => Ok((Verdict::Old, None)),
+        Some(125) => Ok((Verdict::Skip, None)),
+        Some(1..=127) => Ok((Verdict::New, None)),
+        _ => Err(anyhow::anyhow!("predicate {:?} ended with {}, see {:?}", predicate, status, log.path())),
+    }
+}
+
+/// Finds the first of `n` commits, oldest first, for which `test` says
+/// `New`, given that the parent of the first commit is old and the last
+/// commit is new. Commits are tested from the middle of the remaining range
+/// outwards, so skipped ones cost an extra test. If skipped commits hide the
+/// answer, every commit that could be it is returned. The search stops at the
+/// first error of `test`.
+fn search(n: usize, mut test: impl FnMut(usize) -> anyhow::Result<Verdict>) -> anyhow::Result<Result<usize, Vec<usize>>> {
+    // The first new commit is in `lo..=hi`, and `hi` is new.
+    let (mut lo, mut hi) = (0, n - 1);
+    let mut skipped = HashSet::new();
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        let next = (0..hi - lo)
+            .flat_map(|offset| [mid.checked_sub(offset), Some(mid + offset + 1)])
+            .flatten()
+            .find(|index| (lo..hi).contains(index) && !skipped.contains(index));
+        let Some(index) = next else {
+            return Ok(Err((lo..=hi).collect()));
+        };
+
+        match test(index)? {
+            Verdict::Old => lo = index + 1,
+            Verdict::New => hi = index,
+            Verdict::Skip => {
+                skipped.insert(index);
+            }
+        }
+    }
+    Ok(Ok(hi))
+}
+
+fn write_report(out_dir: &Path, report: &BisectReport) -> anyhow::Result<()> {
+    let path = out_dir.join("bisect.json");
+    report::write_json(&path, report)?;
+    println!("succesfully write bisect report to {:?}", path);
+    Ok(())
+}
+
+#[test]
+fn test_search() {
+    let first_new = 5;
+    let verdict = |index: usize| if index >= first_new { Verdict::New } else { Verdict::Old };
+    for n in [6, 7, 10, 33] {
+        assert_eq!(search(n, |index| Ok(verdict(index))).unwrap(), Ok(first_new));
+    }
+    assert_eq!(search(1, |index| Ok(verdict(index))).unwrap(), Ok(0));
+
+    // Commits 4 and 5 don't build, so either could be the first new one.
+    let skipping = |index: usize| if index == 4 || index == 5 { Verdict::Skip } else { verdict(index) };
+    assert_eq!(search(10, |index| Ok(skipping(index))).unwrap(), Err(vec![4, 5, 6]));
+
+    // An interrupted test ends the search instead of skipping the rest.
+    let mut tested = 0;
+    let interrupted = search(33, |index| {
+        tested += 1;
+        if index == 16 { Err(anyhow::anyhow!("interrupted")) } else { Ok(verdict(index)) }
+    });
+    assert!(interrupted.is_err());
+    assert_eq!(tested, 1);
+}
@@ -0,0 +1,71 @@
+use std::{path::Path, fs::read_to_string};
+
+/// Used when `roll_back` is not given `--config-template`.
+const DEFAULT_TEMPLATE: &str = include_str!("../../config.template.toml");
+
+/// Values substituted for the `{{...}}` placeholders of a template.
+pub struct TemplateVars<'a> {
+    pub title: &'a str,
+    pub hash: &'a str,
+    // `cur` or `old`.
+    pub side: &'a str,
+    pub prefix: &'a Path
+}
+
+/// Template of the `config.toml` written into each checkout before building.
+#[derive(Debug, Clone)]
+pub struct ConfigTemplate {
+    text: String
+}
+
+impl ConfigTemplate {
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let text = match path {
+            Some(path) => read_to_string(path)
+                .map_err(|err| anyhow::anyhow!("Fail to read config template {:?}\n{}", path, err))?,
+            None => String::from(DEFAULT_TEMPLATE),
+        };
+        let template = ConfigTemplate { text };
+
+        // Catch unknown placeholders and syntax errors before any checkout.
+        template.render(&TemplateVars {
+            title: "title",
+            hash: "0000000000000000000000000000000000000000",
+            side: "cur",
+            prefix: Path::new("/prefix")
+        })?;
+        Ok(template)
+    }
+
+    /// Substitutes the placeholders and checks the result is valid TOML.
+    pub fn render(&self, vars: &TemplateVars) -> anyhow::Result<String> {
+        let prefix = vars.prefix.to_str()
+            .ok_or_else(|| anyhow::anyhow!("install prefix {:?} is not valid utf8", vars.prefix))?;
+        let rendered = self.text
+            .replace("{{title}}", vars.title)
+            .replace("{{hash}}", vars.hash)
+            .replace("{{side}}", vars.side)
+            .replace("{{prefix}}", prefix);
+
+        if let Some(start) = rendered.find("{{") {
+            let placeholder = rendered[start..].split("}}").next().unwrap_or_default();
+            return Err(anyhow::anyhow!("unknown placeholder {}}}}} in config template", placeholder));
+        }
+        if let Err(err) = rendered.parse::<toml::Value>() {
+            return Err(anyhow::anyhow!("rendered config template is not valid TOML\n{}\n{}", err, rendered));
+        }
+        Ok(rendered)
+    }
+}
+
+#[test]
+fn test_render_template() {
+    let vars = TemplateVars { title: "t", hash: "abc", side: "old", prefix: Path::new("/x/t/abc_old") };
+    let template = ConfigTemplate { text: String::from("[install]\nprefix = \"{{prefix}}\"\n# {{title}} {{hash}} {{side}}\n") };
+    assert_eq!(template.render(&vars).unwrap(), "[install]\nprefix = \"/x/t/abc_old\"\n# t abc old\n");
+
+    let template = ConfigTemplate { text: String::from("prefix = \"{{prefx}}\"\n") };
+    assert!(template.render(&vars).is_err());
+    let template = ConfigTemplate { text: String::from("prefix = {{prefix}}\n") };
+    assert!(template.render(&vars).is_err());
+}
@@ -1,87 +1,233 @@
-use std::{path::PathBuf, process::Command, fs::File, io::{BufReader, Write, BufWriter}};
+use std::{fmt, path::Path, fs::File, io::{BufReader, Write, BufWriter}};
 
-use chrono::{DateTime, Datelike, Duration, TimeZone, Local};
+use chrono::{DateTime, Duration, TimeZone, Local};
 use serde::{Serialize, Deserialize};
 
-use crate::command::command_output::command_output;
+use crate::{command::interrupt, git::{self, BackendKind, CommitInfo, GitBackend, LogQuery}};
+
+use super::matcher::{best_match, score, Candidate, MatchTier};
 
 #[derive(Deserialize, Debug)]
 struct Gitlog {
     // hash: Option<String>,
     title: String,
+    #[serde(default)]
     commits: Vec<String>,
-    date: String,
-    start_date: Option<String>
+    date: Option<String>,
+    start_date: Option<String>,
+    // PR number on rust-lang/rust, resolved through its merge commit.
+    pr: Option<u64>,
+    // Defaults to `merge` for `pr` entries and `commit` otherwise.
+    parent_mode: Option<ParentMode>,
+    // Passed on to the context, see `LogContext::priority`.
+    priority: Option<i64>
+}
+
+/// Where `hash_old` of a pair comes from.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ParentMode {
+    // The first parent of the earliest commit of the PR, paired with the
+    // last commit of the PR.
+    Commit,
+    // The first parent of the merge commit that brought the PR into the
+    // first-parent history, paired with that merge commit.
+    Merge
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LogContext{
+    // Full commit hashes; see `short_cur`/`short_old` for display.
     pub hash_cur: String,
     pub hash_old: String,
-    pub title: String
+    pub title: String,
+    // Which tier of the subject matcher found each side, for auditing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_cur: Option<MatchTier>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_old: Option<MatchTier>,
+    // Number of commits in `hash_old..hash_cur`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<PairFlag>,
+    // Contexts with higher priorities are rolled back first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>
+}
+
+/// Something questionable about a rollback pair that is worth a second look.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PairFlag {
+    NotAncestor,
+    LargeDistance
+}
+
+/// Length of abbreviated hashes shown to users and used in directory names.
+const SHORT_HASH_LEN: usize = 11;
+
+impl LogContext {
+    pub fn new(title: &str, hash_cur: &str, hash_old: &str) -> Self {
+        LogContext {
+            hash_cur: String::from(hash_cur),
+            hash_old: String::from(hash_old),
+            title: String::from(title),
+            match_cur: None,
+            match_old: None,
+            distance: None,
+            flags: vec![],
+            priority: None
+        }
+    }
+
+    pub fn short_cur(&self) -> &str {
+        short_hash(&self.hash_cur)
+    }
+
+    pub fn short_old(&self) -> &str {
+        short_hash(&self.hash_old)
+    }
+}
+
+pub fn short_hash(hash: &str) -> &str {
+    &hash[..hash.len().min(SHORT_HASH_LEN)]
+}
+
+/// Why an entry of the commits json could not be resolved.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FindError {
+    CommitNotFound { commit: String },
+    Ambiguous { commit: String, candidates: Vec<Candidate> },
+    EmptyCommits,
+    NoParent { hash: String },
+    DateParse { date: String, reason: String },
+    MissingDate,
+    Git { reason: String }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Failure {
+    pub title: String,
+    #[serde(flatten)]
+    pub error: FindError,
+    pub message: String
+}
+
+#[derive(Serialize, Debug, Default)]
+struct FindReport {
+    contexts: Vec<LogContext>,
+    failures: Vec<Failure>
 }
 
-pub fn find_commits(repo_dir: &PathBuf, commits_json: &PathBuf, out: &PathBuf) {
-    let mut contexts = vec![];
+const DATE_FMT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Resolves every entry of `commits_json` and writes the found contexts along
+/// with the failed entries to `out`. Pairs further apart than `max_distance`
+/// commits are flagged. Returns whether every entry was resolved.
+pub fn find_commits(repo_dir: &Path, commits_json: &Path, out: &Path, max_distance: u64, backend: BackendKind) -> bool {
+    let mut report = FindReport::default();
 
     let logs = match parse_commit_json(commits_json) {
         Ok(logs) => logs,
         Err(err) => {
             eprintln!("Fail to parse commit json file.\n{}", err);
-            return;
+            return false;
         }
     };
 
-    // let log_contexts = vec![];
+    let git = match git::open(backend, repo_dir) {
+        Ok(git) => git,
+        Err(err) => {
+            eprintln!("Fail to open repo {:?}\n{}", repo_dir, err);
+            return false;
+        }
+    };
 
-    let date_fmt = "%Y-%m-%d %H:%M:%S";
+    // The first-parent history of HEAD is only needed to resolve PRs and merge
+    // mode entries, but then by every one of them, so load it once.
+    let needs_mainline = logs.iter().any(|log| log.pr.is_some() || log.parent_mode == Some(ParentMode::Merge));
+    let mainline = if needs_mainline {
+        match git.log(&LogQuery { first_parent: true, ..LogQuery::default() }) {
+            Ok(mainline) => mainline,
+            Err(err) => {
+                eprintln!("Fail to load the first-parent history of {:?}\n{}", repo_dir, err);
+                return false;
+            }
+        }
+    } else {
+        vec![]
+    };
 
     for log in logs {
-        let date = log.date + " 00:00:00";
-        match get_context_log(
-            repo_dir, 
-            &log.title, 
-            &log.commits,
-            match Local.datetime_from_str(&date, date_fmt) {
-                Ok(date) => date,
-                Err(err) => {
-                    eprintln!("Fail to parse date string {}\n{}", &date, err);
-                    continue;
-                }
-            },
-            match log.start_date {
-                Some(start_date) => {
-                    let start_date = start_date + " 00:00:00";
-                    match Local.datetime_from_str(&start_date, date_fmt) {
-                        Ok(date) => Some(date),
-                        Err(err) => {
-                            eprintln!("Fail to parse start_date string {}\n{}", &date, err);
-                            continue;
-                        }
-                    }
+        if interrupt::is_interrupted() {
+            eprintln!("interrupted, {:?} is left as it was", out);
+            return false;
+        }
+        match get_context(git.as_ref(), &mainline, &log).and_then(|context| check_pair(git.as_ref(), context, max_distance)) {
+            Ok(mut context) => {
+                context.priority = log.priority;
+                if !context.flags.is_empty() {
+                    eprintln!("warning: {} {}..{} is flagged {:?}", context.title, context.short_old(), context.short_cur(), context.flags);
                 }
-                None => None,
-            }
-        ) {
-            Ok(context) => {
-                contexts.push(context);
+                report.contexts.push(context);
             },
             Err(err) => {
-                eprintln!("Fail to find context commit log for {}\n{}", log.title, err);                    
-                continue;
+                eprintln!("Fail to find context commit log for {}\n{}", log.title, err);
+                report.failures.push(Failure {
+                    title: log.title,
+                    message: err.to_string(),
+                    error: err
+                });
             }
         }
     }
 
-    match write_context(out, contexts){
-        Ok(()) => (),
+    let succeeded = report.failures.is_empty();
+    match write_context(out, &report){
+        Ok(()) => succeeded,
         Err(err) => {
             eprintln!("Fail to write commit contexts to file {:?}\n{}", out, err);
+            false
         }
     }
 }
 
-fn parse_commit_json(commits_json: &PathBuf) -> anyhow::Result<Vec<Gitlog>> {
+/// `mainline` is the first-parent history of HEAD, newest first.
+fn get_context(git: &dyn GitBackend, mainline: &[CommitInfo], log: &Gitlog) -> Result<LogContext, FindError> {
+    if let Some(pr) = log.pr {
+        return get_context_pr(git, mainline, &log.title, pr, log.parent_mode.unwrap_or(ParentMode::Merge));
+    }
+
+    let date = match &log.date {
+        Some(date) => parse_date(date)?,
+        None => return Err(FindError::MissingDate),
+    };
+    let start_date = match &log.start_date {
+        Some(start_date) => Some(parse_date(start_date)?),
+        None => None,
+    };
+    get_context_log(git, mainline, &log.title, &log.commits, date, start_date, log.parent_mode.unwrap_or(ParentMode::Commit))
+}
+
+/// Checks that `hash_old` is reachable from `hash_cur` and records how many
+/// commits lie between them.
+fn check_pair(git: &dyn GitBackend, mut context: LogContext, max_distance: u64) -> Result<LogContext, FindError> {
+    if !git.is_ancestor(&context.hash_old, &context.hash_cur)? {
+        context.flags.push(PairFlag::NotAncestor);
+    }
+
+    let distance = git.count(&context.hash_old, &context.hash_cur)?;
+    if distance > max_distance {
+        context.flags.push(PairFlag::LargeDistance);
+    }
+    context.distance = Some(distance);
+
+    Ok(context)
+}
+
+fn parse_commit_json(commits_json: &Path) -> anyhow::Result<Vec<Gitlog>> {
     let fptr = File::open(commits_json)?;
     let reader = BufReader::new(fptr);
 
@@ -89,88 +235,220 @@ fn parse_commit_json(commits_json: &PathBuf) -> anyhow::Result<Vec<Gitlog>> {
     Ok(logs)
 }
 
-fn get_context_log(repo_dir: &PathBuf, title: &String, commit_titles: &Vec<String>, date: DateTime<Local>, start_date: Option<DateTime<Local>>) -> anyhow::Result<LogContext> {
-    let last_day;
-    match start_date {
-        Some(start_date) => last_day = start_date - Duration::days(1),
-        None => last_day = date - Duration::days(1)
-    }
-    // let next_day = date + Duration::days(1);
+fn parse_date(date: &str) -> Result<DateTime<Local>, FindError> {
+    Local.datetime_from_str(&(date.to_string() + " 00:00:00"), DATE_FMT)
+        .map_err(|err| FindError::DateParse { date: String::from(date), reason: err.to_string() })
+}
 
-    let mut cmd = Command::new("git");
-    cmd.current_dir(repo_dir)
-        .arg("log")
-        .arg("--oneline")
-        .arg("--before")
-        .arg(format!("{}-{}-{}", date.year(), date.month(), date.day()))
-        .arg("--after")
-        .arg(format!("{}-{}-{}", last_day.year(), last_day.month(), last_day.day()));
-        
-    // println!("searching {}, commits{:?}, cmd = {:?}", title, commit_titles, &cmd);
-
-    let output = match command_output(&mut cmd){
-        Ok(output) => output,
-        Err(err) => return Err(err),
+/// Locates the bors (`Auto merge of #N`) or GitHub (`Merge pull request #N`)
+/// merge commit of PR `pr` on the first-parent history. In merge mode the
+/// merge itself is `hash_cur` and its first parent, i.e. master right before
+/// the PR landed, is `hash_old`. In commit mode the PR branch merged by it is
+/// rolled back instead.
+fn get_context_pr(git: &dyn GitBackend, mainline: &[CommitInfo], title: &str, pr: u64, mode: ParentMode) -> Result<LogContext, FindError> {
+    let merge = match mainline.iter().find(|commit| merged_pr(&commit.subject) == Some(pr)) {
+        Some(merge) => merge,
+        None => return Err(FindError::CommitNotFound { commit: format!("#{}", pr) }),
     };
 
-    let stdout = String::from_utf8(output.stdout.clone()).expect("utf8 output");
+    match (mode, merge.parents.as_slice()) {
+        (ParentMode::Merge, [first, ..]) => Ok(LogContext::new(title, &merge.hash, first)),
+        (ParentMode::Commit, [first, branch, ..]) => {
+            let earliest = match git.range(first, branch)?.into_iter().next() {
+                Some(earliest) => earliest,
+                None => return Err(FindError::CommitNotFound {
+                    commit: format!("{}..{}", short_hash(first), short_hash(branch))
+                }),
+            };
+            Ok(LogContext::new(title, branch, &first_parent(git, &earliest)?))
+        }
+        _ => Err(FindError::NoParent { hash: merge.hash.clone() }),
+    }
+}
 
-    let mut iter = stdout.lines().into_iter();
-    let mut line = iter.next();
+/// Returns the PR number merged by a bors or GitHub merge commit subject.
+fn merged_pr(subject: &str) -> Option<u64> {
+    let rest = subject.strip_prefix("Auto merge of #")
+        .or_else(|| subject.strip_prefix("Merge pull request #"))?;
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+fn get_context_log(git: &dyn GitBackend, mainline: &[CommitInfo], title: &str, commit_titles: &[String], date: DateTime<Local>, start_date: Option<DateTime<Local>>, mode: ParentMode) -> Result<LogContext, FindError> {
+    let last_day = match start_date {
+        Some(start_date) => start_date - Duration::days(1),
+        None => date - Duration::days(1)
+    };
+    // let next_day = date + Duration::days(1);
 
-    let old_commit_title = commit_titles.first().unwrap();
-    let cur_commit_title = commit_titles.last().unwrap();
+    let commits = git.log(&LogQuery {
+        after: Some(last_day.date_naive()),
+        before: Some(date.date_naive()),
+        ..LogQuery::default()
+    })?;
 
-    let mut old_commit_hash = "";
-    let mut cur_commit_hash = "";
+    let (old_commit_title, cur_commit_title) = match (commit_titles.first(), commit_titles.last()) {
+        (Some(old), Some(cur)) => (old, cur),
+        _ => return Err(FindError::EmptyCommits),
+    };
 
-    while line != None {
-        let s = line.unwrap();
+    let cur = match_commit(&commits, cur_commit_title)?;
 
-        if s.contains(cur_commit_title) {
-            cur_commit_hash = &s[0..s.find(' ').unwrap()];
+    let mut context = match mode {
+        ParentMode::Commit => {
+            let old = match_commit(&commits, old_commit_title)?;
+            let mut context = LogContext::new(title, &cur.hash, &first_parent(git, &old.hash)?);
+            context.match_old = Some(old.tier);
+            context
         }
-        if s.contains(old_commit_title) {
-            line = iter.next();
-            let s = line.unwrap();
-            old_commit_hash = &s[0..s.find(' ').unwrap()];
-            continue;
+        // The merge alone decides both sides, the old commit title is unused.
+        ParentMode::Merge => {
+            let merge = mainline_merge(git, mainline, &cur.hash)?;
+            LogContext::new(title, &merge, &first_parent(git, &merge)?)
         }
-        line = iter.next();   
     };
+    context.match_cur = Some(cur.tier);
+    Ok(context)
+}
 
-    if cur_commit_hash.len() <= 0 {
-        eprintln!("Fail to find commit hash for {}-{}", title, cur_commit_title);
+fn match_commit(commits: &[CommitInfo], commit_title: &str) -> Result<Candidate, FindError> {
+    let candidates = commits.iter()
+        .filter_map(|commit| {
+            let (tier, score) = score(commit_title, &commit.subject)?;
+            Some(Candidate {
+                hash: commit.hash.clone(),
+                author: commit.author.clone(),
+                date: commit.date.clone(),
+                subject: commit.subject.clone(),
+                tier,
+                score
+            })
+        })
+        .collect();
+
+    match best_match(candidates) {
+        Ok(Some(candidate)) => Ok(candidate),
+        Ok(None) => Err(FindError::CommitNotFound { commit: String::from(commit_title) }),
+        Err(candidates) => Err(FindError::Ambiguous { commit: String::from(commit_title), candidates }),
     }
+}
 
-    if old_commit_hash.len() <= 0 {
-        eprintln!("Fail to find commit hash for {}-{}", title, old_commit_title);
+fn first_parent(git: &dyn GitBackend, hash: &str) -> Result<String, FindError> {
+    match git.first_parent(hash)? {
+        Some(parent) => Ok(parent),
+        None => Err(FindError::NoParent { hash: String::from(hash) }),
     }
-    
-    assert!(cur_commit_hash.len() > 0);
-    assert!(old_commit_hash.len() > 0);
+}
 
-    Ok(LogContext { 
-        hash_cur: String::from(cur_commit_hash), 
-        hash_old: String::from(old_commit_hash),
-        title: title.clone()
-    })
+/// The commit of the first-parent history of HEAD that brought `hash` in,
+/// which is `hash` itself when it was committed to the first-parent history.
+fn mainline_merge(git: &dyn GitBackend, mainline: &[CommitInfo], hash: &str) -> Result<String, FindError> {
+    // Newest first, and every commit newer than the merge contains `hash`,
+    // so the merge is the last one of the leading run containing it.
+    let (mut lo, mut hi) = (0, mainline.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if git.is_ancestor(hash, &mainline[mid].hash)? {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    match lo.checked_sub(1) {
+        Some(merge) => Ok(mainline[merge].hash.clone()),
+        None => Err(FindError::CommitNotFound { commit: format!("merge of {}", short_hash(hash)) }),
+    }
 }
 
-fn write_context(out: &PathBuf, contexts: Vec<LogContext>) -> anyhow::Result<()> {
+fn write_context(out: &Path, report: &FindReport) -> anyhow::Result<()> {
     let fptr = File::create(out)?;
     let mut writer = BufWriter::new(fptr);
 
-    writer.write(serde_json::to_string(&contexts)?.as_bytes())?;
+    writer.write_all(serde_json::to_string(report)?.as_bytes())?;
 
     Ok(())
 }
 
+impl fmt::Display for FindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FindError::CommitNotFound { commit } => write!(f, "no commit matches {}", commit),
+            FindError::Ambiguous { commit, candidates } => {
+                write!(f, "{} commits match {}:", candidates.len(), commit)?;
+                for candidate in candidates {
+                    write!(f, "\n    {}", candidate)?;
+                }
+                Ok(())
+            }
+            FindError::EmptyCommits => write!(f, "`commits` is empty"),
+            FindError::NoParent { hash } => write!(f, "{} has no parent to roll back to", short_hash(hash)),
+            FindError::DateParse { date, reason } => write!(f, "fail to parse date string {}: {}", date, reason),
+            FindError::MissingDate => write!(f, "either `pr` or `date` is required"),
+            FindError::Git { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FindError {}
+
+impl From<anyhow::Error> for FindError {
+    fn from(err: anyhow::Error) -> Self {
+        FindError::Git { reason: format!("{:#}", err) }
+    }
+}
+
 #[test]
 fn test_find_commits() {
     find_commits(
-        &PathBuf::from("/media/workstation/device/home/fxl/rustc/rust"),
-        &PathBuf::from("/media/workstation/device/home/fxl/CommitRoller/commit_roller/commit_info.json"),
-        &PathBuf::from("/media/workstation/device/home/fxl/CommitRoller/commit_roller/out")
-    )
-}
\ No newline at end of file
+        Path::new("/media/workstation/device/home/fxl/rustc/rust"),
+        Path::new("/media/workstation/device/home/fxl/CommitRoller/commit_roller/commit_info.json"),
+        Path::new("/media/workstation/device/home/fxl/CommitRoller/commit_roller/out"),
+        200,
+        BackendKind::Cli
+    );
+}
+
+#[test]
+fn test_merged_pr() {
+    assert_eq!(merged_pr("Auto merge of #91288 - cjgillot:owner-macro, r=petrochenkov"), Some(91288));
+    assert_eq!(merged_pr("Merge pull request #12 from rust-lang/foo"), Some(12));
+    assert_eq!(merged_pr("Rollup merge of #91288 - cjgillot:owner-macro"), None);
+    assert_eq!(merged_pr("Auto merge of #abc"), None);
+}
+
+#[test]
+fn test_parent_modes() {
+    let repo_dir = std::env::temp_dir().join(format!("commit_roller_parent_modes_{}", std::process::id()));
+    git::fixture_repo(&repo_dir);
+    let git = git::open(BackendKind::Cli, &repo_dir).unwrap();
+    let mainline = git.log(&LogQuery { first_parent: true, ..LogQuery::default() }).unwrap();
+    let hash = |subject: &str| git.log(&LogQuery::default()).unwrap().into_iter()
+        .find(|commit| commit.subject.starts_with(subject))
+        .unwrap()
+        .hash;
+    let (init, unrelated, tests, merge) = (hash("init"), hash("Unrelated"), hash("Update tests"), hash("Auto merge"));
+
+    let context = |entry: &str| {
+        let log: Gitlog = serde_json::from_str(entry).unwrap();
+        get_context(git.as_ref(), &mainline, &log).unwrap()
+    };
+    let entry = |mode: &str| format!(
+        r#"{{"title": "t", "commits": ["Treat macros", "Update tests"], "date": "2021-11-04", "start_date": "2021-10-30", "parent_mode": "{}"}}"#,
+        mode
+    );
+
+    let commit = context(&entry("commit"));
+    assert_eq!((commit.hash_cur.as_str(), commit.hash_old.as_str()), (tests.as_str(), init.as_str()));
+    assert_eq!(commit.match_old, Some(MatchTier::Prefix));
+
+    let merged = context(&entry("merge"));
+    assert_eq!((merged.hash_cur.as_str(), merged.hash_old.as_str()), (merge.as_str(), unrelated.as_str()));
+    assert_eq!(merged.match_old, None);
+
+    let pr = context(r#"{"title": "t", "pr": 87234, "parent_mode": "commit"}"#);
+    assert_eq!((pr.hash_cur.as_str(), pr.hash_old.as_str()), (tests.as_str(), init.as_str()));
+    let pr = context(r#"{"title": "t", "pr": 87234}"#);
+    assert_eq!((pr.hash_cur.as_str(), pr.hash_old.as_str()), (merge.as_str(), unrelated.as_str()));
+
+    std::fs::remove_dir_all(&repo_dir).unwrap();
+}
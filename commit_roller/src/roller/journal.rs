@@ -0,0 +1,159 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, fs::{File, OpenOptions}, io::{BufRead, BufReader, Write}, sync::Mutex};
+
+use chrono::Local;
+use serde::{Serialize, Deserialize};
+
+use crate::command::interrupt;
+
+use super::{gitlog::LogContext, stash::Side};
+
+/// Steps of rolling back one side of a context, in the order they happen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    // Workspace created and `config.toml` written.
+    Copied,
+    CheckedOut,
+    // Every recipe step but the last.
+    Built,
+    // The last recipe step.
+    Installed,
+    // Workspace removed.
+    Cleaned
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Failed,
+    // Stopped by SIGINT/SIGTERM.
+    Interrupted
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    time: String,
+    title: String,
+    hash: String,
+    side: String,
+    phase: Phase,
+    outcome: Outcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>
+}
+
+type Key = (String, String, String, Phase);
+
+/// Append-only record of every phase of a `roll_back` run, one JSON object
+/// per line so that a crash loses at most the line being written.
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    // Latest outcome of every phase.
+    outcomes: Mutex<HashMap<Key, Outcome>>
+}
+
+impl Journal {
+    /// Opens the journal of `out_dir`. Resuming keeps what an earlier run
+    /// recorded, otherwise the journal starts over.
+    pub fn open(out_dir: &Path, resume: bool) -> anyhow::Result<Self> {
+        let path = out_dir.join("journal.jsonl");
+
+        let mut outcomes = HashMap::new();
+        if resume && path.exists() {
+            for (number, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+                let line = line?;
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        outcomes.insert((entry.title, entry.hash, entry.side, entry.phase), entry.outcome);
+                    }
+                    // Most likely the last line, cut short by a crash.
+                    Err(err) => eprintln!("warning: skip line {} of {:?}\n{}", number + 1, path, err),
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(&path)?;
+        Ok(Journal { path, file: Mutex::new(file), outcomes: Mutex::new(outcomes) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `phase` of the side of `context` last finished successfully.
+    pub fn is_done(&self, context: &LogContext, side: Side, phase: Phase) -> bool {
+        self.outcomes.lock().unwrap().get(&key(context, side, phase)) == Some(&Outcome::Ok)
+    }
+
+    /// Records the outcome of `phase`. Failing to write the journal only
+    /// costs redoing the phase on resume, so it is reported and ignored.
+    pub fn record<T>(&self, context: &LogContext, side: Side, phase: Phase, result: &anyhow::Result<T>) {
+        let outcome = match result {
+            Ok(_) => Outcome::Ok,
+            Err(_) if interrupt::is_interrupted() => Outcome::Interrupted,
+            Err(_) => Outcome::Failed,
+        };
+        let entry = Entry {
+            time: Local::now().to_rfc3339(),
+            title: context.title.clone(),
+            hash: String::from(side.hash(context)),
+            side: String::from(side.name()),
+            phase,
+            outcome,
+            message: result.as_ref().err().map(|err| format!("{:#}", err))
+        };
+
+        self.outcomes.lock().unwrap().insert(key(context, side, phase), outcome);
+
+        let written = serde_json::to_string(&entry).map_err(anyhow::Error::from).and_then(|line| {
+            let mut file = self.file.lock().unwrap();
+            writeln!(file, "{}", line)?;
+            file.sync_data()?;
+            Ok(())
+        });
+        if let Err(err) = written {
+            eprintln!("warning: fail to write journal {:?}\n{}", self.path, err);
+        }
+    }
+
+    /// Forgets every phase of the side of `context`, e.g. when its workspace
+    /// has to be made again.
+    pub fn reset(&self, context: &LogContext, side: Side) {
+        let (title, hash, side) = (context.title.as_str(), side.hash(context), side.name());
+        self.outcomes.lock().unwrap().retain(|(t, h, s, _), _| (t.as_str(), h.as_str(), s.as_str()) != (title, hash, side));
+    }
+}
+
+fn key(context: &LogContext, side: Side, phase: Phase) -> Key {
+    (context.title.clone(), String::from(side.hash(context)), String::from(side.name()), phase)
+}
+
+#[test]
+fn test_journal_resume() {
+    let out_dir = std::env::temp_dir().join(format!("commit_roller_journal_{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let context = LogContext::new("t", "7891aae", "6965b7a");
+
+    let journal = Journal::open(&out_dir, false).unwrap();
+    journal.record(&context, Side::Cur, Phase::Copied, &Ok(()));
+    journal.record(&context, Side::Cur, Phase::Built, &Err::<(), _>(anyhow::anyhow!("step build failed")));
+    drop(journal);
+
+    let journal = Journal::open(&out_dir, true).unwrap();
+    assert!(journal.is_done(&context, Side::Cur, Phase::Copied));
+    assert!(!journal.is_done(&context, Side::Cur, Phase::Built));
+    assert!(!journal.is_done(&context, Side::Old, Phase::Copied));
+    drop(journal);
+
+    let journal = Journal::open(&out_dir, false).unwrap();
+    assert!(!journal.is_done(&context, Side::Cur, Phase::Copied));
+
+    std::fs::remove_dir_all(&out_dir).unwrap();
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+
+use super::gitlog::short_hash;
+
+/// How closely a commit subject matched the title given in the commits json,
+/// ordered from the weakest to the strongest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchTier {
+    Fuzzy,
+    Normalized,
+    Prefix,
+    Exact
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Candidate {
+    pub hash: String,
+    pub author: String,
+    pub date: String,
+    pub subject: String,
+    pub tier: MatchTier,
+    pub score: f64
+}
+
+/// Minimal similarity of two normalized subjects to be a fuzzy match.
+const FUZZY_THRESHOLD: f64 = 0.8;
+
+/// Scores `subject` against the wanted title. Scores lie in `[0, 1]` and never
+/// overlap between tiers, so comparing scores also compares tiers.
+pub fn score(wanted: &str, subject: &str) -> Option<(MatchTier, f64)> {
+    let (wanted, subject) = (wanted.trim(), subject.trim());
+    if wanted.is_empty() {
+        return None;
+    }
+    if subject == wanted {
+        return Some((MatchTier::Exact, 1.0));
+    }
+    if subject.starts_with(wanted) {
+        return Some((MatchTier::Prefix, 0.75 + 0.2 * ratio(wanted, subject)));
+    }
+
+    let (wanted, subject) = (normalize(wanted), normalize(subject));
+    if subject == wanted {
+        return Some((MatchTier::Normalized, 0.7));
+    }
+    if subject.starts_with(&wanted) {
+        return Some((MatchTier::Normalized, 0.5 + 0.2 * ratio(&wanted, &subject)));
+    }
+
+    let similarity = strsim::normalized_levenshtein(&wanted, &subject);
+    if similarity >= FUZZY_THRESHOLD {
+        return Some((MatchTier::Fuzzy, 0.5 * similarity));
+    }
+    if subject.contains(&wanted) {
+        return Some((MatchTier::Fuzzy, 0.4 * ratio(&wanted, &subject)));
+    }
+    None
+}
+
+/// Picks the only candidate. More than one candidate is ambiguous, even when
+/// they matched in different tiers, in which case every candidate is returned,
+/// best first.
+pub fn best_match(mut candidates: Vec<Candidate>) -> Result<Option<Candidate>, Vec<Candidate>> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    if candidates.len() > 1 {
+        return Err(candidates);
+    }
+    Ok(candidates.pop())
+}
+
+/// Lowercases, drops backticks and collapses whitespace.
+fn normalize(s: &str) -> String {
+    s.replace('`', "")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn ratio(short: &str, long: &str) -> f64 {
+    short.chars().count() as f64 / long.chars().count().max(1) as f64
+}
+
+impl fmt::Display for MatchTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MatchTier::Fuzzy => "fuzzy",
+            MatchTier::Normalized => "normalized",
+            MatchTier::Prefix => "prefix",
+            MatchTier::Exact => "exact",
+        };
+        f.write_str(s)
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {:.3} ({}) {}", short_hash(&self.hash), self.author, self.date, self.score, self.tier, self.subject)
+    }
+}
+
+#[test]
+fn test_score_tiers() {
+    let tier = |wanted, subject| score(wanted, subject).map(|(tier, _)| tier);
+    assert_eq!(tier("Update tests", "Update tests"), Some(MatchTier::Exact));
+    assert_eq!(tier("Update tests", "Update tests for foo"), Some(MatchTier::Prefix));
+    assert_eq!(tier("Use `is_` prefix", "use is_  prefix"), Some(MatchTier::Normalized));
+    assert_eq!(tier("Amend codegen test", "Amend codegen tests"), Some(MatchTier::Prefix));
+    assert_eq!(tier("Amend codegen tests", "Amend codegen test"), Some(MatchTier::Fuzzy));
+    assert_eq!(tier("Update tests", "Remove HirItemLike"), None);
+}
+
+#[test]
+fn test_best_match_mixed_tiers() {
+    let candidate = |hash: &str, subject: &str| {
+        let (tier, score) = score("Update tests", subject).unwrap();
+        Candidate { hash: String::from(hash), author: String::new(), date: String::new(), subject: String::from(subject), tier, score }
+    };
+
+    assert_eq!(best_match(vec![]).unwrap().map(|c| c.hash), None);
+    assert_eq!(best_match(vec![candidate("a", "Update tests for foo")]).unwrap().map(|c| c.hash), Some(String::from("a")));
+
+    let candidates = best_match(vec![candidate("a", "Update tests for foo"), candidate("b", "Update tests")]).unwrap_err();
+    let tiers: Vec<(&str, MatchTier)> = candidates.iter().map(|c| (c.hash.as_str(), c.tier)).collect();
+    assert_eq!(tiers, [("b", MatchTier::Exact), ("a", MatchTier::Prefix)]);
+}
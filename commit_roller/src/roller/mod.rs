@@ -1,2 +1,11 @@
+pub mod bisect;
+pub mod config;
 pub mod gitlog;
-pub mod stash;
\ No newline at end of file
+pub mod journal;
+pub mod matcher;
+pub mod recipe;
+pub mod scheduler;
+pub mod stash;
+pub mod store;
+pub mod summary;
+pub mod workspace;
\ No newline at end of file
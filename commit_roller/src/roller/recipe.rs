@@ -0,0 +1,178 @@
+use std::{collections::BTreeMap, ffi::OsStr, path::{Path, PathBuf}, fs::read_to_string, process::Command, time::Duration};
+
+use anyhow::Context;
+use serde::{Serialize, Deserialize};
+
+use crate::command::{command_output::command_output_logged, diagnose::{find_in_path, is_executable}, log::CommandLog};
+
+/// One command of a recipe. `{{prefix}}` in `args` and `env` is replaced by
+/// the install prefix of the toolchain being built and `{{jobs}}` by the
+/// number of parallel build jobs granted to it. A `program` of `x.py` runs
+/// the bootstrap entry point of the checkout, see `x_entry_point`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildStep {
+    pub name: String,
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    // Relative to the checkout.
+    pub working_dir: Option<PathBuf>,
+    // In seconds.
+    pub timeout: Option<u64>
+}
+
+/// How a checkout is built and installed, as an ordered list of steps.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BuildRecipe {
+    pub steps: Vec<BuildStep>,
+    // Whether the rendered config template is written as `config.toml`,
+    // which only x.py reads.
+    #[serde(default = "default_write_config")]
+    pub write_config: bool,
+    // Interpreter of `x.py` steps, looked up in `PATH` when unset.
+    #[serde(default)]
+    pub python: Option<PathBuf>,
+    // Timeout in seconds of steps without a timeout of their own.
+    #[serde(default)]
+    pub timeout: Option<u64>
+}
+
+fn default_write_config() -> bool {
+    true
+}
+
+impl BuildRecipe {
+    /// Loads one of the presets `rustc`, `cargo`, `make` and `cmake`, or
+    /// otherwise a recipe TOML file at `name`.
+    pub fn load(name: &str) -> anyhow::Result<Self> {
+        let recipe = match name {
+            "rustc" => BuildRecipe::new(true, &[
+                ("build", "x.py", &["build", "-j", "{{jobs}}"]),
+                ("install", "x.py", &["install", "-j", "{{jobs}}"]),
+            ]),
+            "cargo" => BuildRecipe::new(false, &[
+                ("build", "cargo", &["build", "--release", "-j", "{{jobs}}"]),
+                ("install", "cargo", &["install", "--path", ".", "--root", "{{prefix}}", "-j", "{{jobs}}"]),
+            ]),
+            "make" => BuildRecipe::new(false, &[
+                ("build", "make", &["-j{{jobs}}"]),
+                ("install", "make", &["install", "prefix={{prefix}}"]),
+            ]),
+            "cmake" => BuildRecipe::new(false, &[
+                ("configure", "cmake", &["-S", ".", "-B", "build", "-DCMAKE_BUILD_TYPE=Release", "-DCMAKE_INSTALL_PREFIX={{prefix}}"]),
+                ("build", "cmake", &["--build", "build", "-j", "{{jobs}}"]),
+                ("install", "cmake", &["--install", "build"]),
+            ]),
+            path => {
+                let text = read_to_string(path)
+                    .map_err(|err| anyhow::anyhow!("Fail to read build recipe {:?}\n{}", path, err))?;
+                toml::from_str(&text)
+                    .map_err(|err| anyhow::anyhow!("Fail to parse build recipe {:?}\n{}", path, err))?
+            }
+        };
+        if recipe.steps.is_empty() {
+            return Err(anyhow::anyhow!("build recipe {} has no steps", name));
+        }
+        Ok(recipe)
+    }
+
+    fn new(write_config: bool, steps: &[(&str, &str, &[&str])]) -> Self {
+        let steps = steps.iter()
+            .map(|(name, program, args)| BuildStep {
+                name: String::from(*name),
+                program: String::from(*program),
+                args: args.iter().map(|arg| String::from(*arg)).collect(),
+                env: BTreeMap::new(),
+                working_dir: None,
+                timeout: None
+            })
+            .collect();
+        BuildRecipe { steps, write_config, python: None, timeout: None }
+    }
+
+    /// Every step but the last one.
+    pub fn build_steps(&self) -> &[BuildStep] {
+        &self.steps[..self.steps.len() - 1]
+    }
+
+    /// The last step, which is expected to install the toolchain.
+    pub fn install_steps(&self) -> &[BuildStep] {
+        &self.steps[self.steps.len() - 1..]
+    }
+
+    /// Runs `steps` of this recipe in `dir`, stopping at the first failing one.
+    /// The output of each step goes to `<logs>/<step>.log`, of which the last
+    /// `tail` lines are part of the error of a failing step.
+    pub fn run(&self, steps: &[BuildStep], dir: &Path, prefix: &Path, jobs: usize, logs: &Path, tail: usize) -> anyhow::Result<()> {
+        let prefix = prefix.to_string_lossy();
+        let jobs = jobs.to_string();
+        let render = |s: &str| s.replace("{{prefix}}", &prefix).replace("{{jobs}}", &jobs);
+
+        for step in steps {
+            let working_dir = match &step.working_dir {
+                Some(working_dir) => dir.join(working_dir),
+                None => dir.to_path_buf(),
+            };
+            let mut cmd = if is_x_py(&step.program) {
+                x_entry_point(&working_dir, self.python.as_deref())
+                    .map_err(|err| anyhow::anyhow!("step {} failed\n{}", step.name, err))?
+            } else {
+                Command::new(&step.program)
+            };
+            cmd.current_dir(&working_dir);
+            cmd.args(step.args.iter().map(|arg| render(arg)));
+            cmd.envs(step.env.iter().map(|(key, value)| (key, render(value))));
+
+            let mut log = CommandLog::create(&logs.join(format!("{}.log", step.name)), tail)?;
+            command_output_logged(&mut cmd, step.timeout.or(self.timeout).map(Duration::from_secs), &mut log)
+                .with_context(|| format!("step {} failed", step.name))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_x_py(program: &str) -> bool {
+    program == "x.py" || program == "./x.py"
+}
+
+/// Interpreters tried in order when none is configured.
+const PYTHONS: [&str; 3] = ["python3", "python", "python2"];
+
+/// Runs `x.py` through a python interpreter rather than relying on its
+/// executable bit and shebang, which old checkouts may lack or point to a
+/// python that is not installed. Checkouts that also ship the `x` wrapper
+/// script fall back to it when no interpreter is found.
+fn x_entry_point(dir: &Path, python: Option<&Path>) -> anyhow::Result<Command> {
+    let python = match python {
+        Some(python) if python.components().count() > 1 && !is_executable(python) =>
+            return Err(anyhow::anyhow!("configured python {:?} is not an executable file", python)),
+        Some(python) => Some(python.to_path_buf()),
+        None => PYTHONS.iter().find_map(|name| find_in_path(OsStr::new(name))),
+    };
+
+    if dir.join("x.py").is_file() {
+        if let Some(python) = python {
+            let mut cmd = Command::new(python);
+            cmd.arg("x.py");
+            return Ok(cmd);
+        }
+    }
+    if cfg!(unix) && dir.join("x").is_file() {
+        let mut cmd = Command::new("sh");
+        cmd.arg("x");
+        return Ok(cmd);
+    }
+    if cfg!(windows) && dir.join("x.ps1").is_file() {
+        let mut cmd = Command::new("powershell");
+        cmd.arg("-NoProfile").arg("-File").arg("x.ps1");
+        return Ok(cmd);
+    }
+
+    if dir.join("x.py").is_file() {
+        Err(anyhow::anyhow!("no python interpreter to run x.py: none of {} is in PATH, pass --python", PYTHONS.join(", ")))
+    } else {
+        Err(anyhow::anyhow!("{:?} has no x.py, x or x.ps1 entry point", dir))
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::VecDeque, path::Path, sync::{Condvar, Mutex}, thread};
+
+/// How many rollback jobs run at once and how much they may use.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    // Jobs building concurrently.
+    pub jobs: usize,
+    // `{{jobs}}` handed to the build of each job.
+    pub build_jobs: usize,
+    // Bytes that must stay free on the filesystem of the out dir.
+    pub min_free_space: u64,
+    // Bytes a single job is expected to take, reserved while it runs.
+    pub job_disk_estimate: u64
+}
+
+impl Budget {
+    /// Splits the available CPUs evenly between `jobs` jobs unless
+    /// `build_jobs` is given.
+    pub fn new(jobs: usize, build_jobs: Option<usize>, min_free_space: u64, job_disk_estimate: u64) -> Self {
+        let jobs = jobs.max(1);
+        let cpus = thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1);
+        Budget {
+            jobs,
+            build_jobs: build_jobs.unwrap_or(cpus / jobs).max(1),
+            min_free_space,
+            job_disk_estimate
+        }
+    }
+}
+
+struct State<J> {
+    queue: VecDeque<J>,
+    running: usize
+}
+
+/// Runs `work` on every job, at most `budget.jobs` at a time and in the given
+/// order. A job only starts when the filesystem of `disk` keeps
+/// `min_free_space` after reserving `job_disk_estimate` for it and for every
+/// running job; otherwise it waits for a running job to finish, or is handed
+/// to `refuse` when nothing is running that could free space.
+pub fn run<J, W, R>(jobs: Vec<J>, budget: &Budget, disk: &Path, work: W, refuse: R)
+where
+    J: Send,
+    W: Fn(J) + Sync,
+    R: Fn(J, anyhow::Error) + Sync,
+{
+    schedule(jobs, budget, disk, || free_space(disk), work, refuse)
+}
+
+/// `run` with the free space of `disk` measured by `free_space`.
+fn schedule<J, F, W, R>(jobs: Vec<J>, budget: &Budget, disk: &Path, free_space: F, work: W, refuse: R)
+where
+    J: Send,
+    F: Fn() -> anyhow::Result<u64> + Sync,
+    W: Fn(J) + Sync,
+    R: Fn(J, anyhow::Error) + Sync,
+{
+    let state = Mutex::new(State { queue: VecDeque::from(jobs), running: 0 });
+    let finished = Condvar::new();
+
+    let next = || -> Next<J> {
+        let mut state = state.lock().unwrap();
+        loop {
+            if state.queue.is_empty() {
+                return Next::Done;
+            }
+            match check_disk(budget, disk, &free_space, state.running) {
+                Ok(()) => {
+                    state.running += 1;
+                    return state.queue.pop_front().map_or(Next::Done, Next::Run);
+                }
+                Err(err) if state.running == 0 => {
+                    return state.queue.pop_front().map_or(Next::Done, |job| Next::Refuse(job, err));
+                }
+                Err(_) => state = finished.wait(state).unwrap(),
+            }
+        }
+    };
+
+    thread::scope(|scope| {
+        for _ in 0..budget.jobs {
+            scope.spawn(|| loop {
+                match next() {
+                    Next::Run(job) => {
+                        work(job);
+                        state.lock().unwrap().running -= 1;
+                        finished.notify_all();
+                    }
+                    Next::Refuse(job, err) => refuse(job, err),
+                    Next::Done => break,
+                }
+            });
+        }
+    });
+}
+
+enum Next<J> {
+    Run(J),
+    Refuse(J, anyhow::Error),
+    Done
+}
+
+fn check_disk(budget: &Budget, disk: &Path, free_space: impl Fn() -> anyhow::Result<u64>, running: usize) -> anyhow::Result<()> {
+    if budget.min_free_space == 0 && budget.job_disk_estimate == 0 {
+        return Ok(());
+    }
+    let free = free_space()?;
+    let needed = budget.min_free_space + budget.job_disk_estimate * (running as u64 + 1);
+    if free < needed {
+        return Err(anyhow::anyhow!(
+            "{} bytes free on the filesystem of {:?}, {} needed to keep {} free with {} jobs",
+            free, disk, needed, budget.min_free_space, running + 1
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn free_space(path: &Path) -> anyhow::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let path_c = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path_c.as_ptr(), &mut stat) } != 0 {
+        return Err(anyhow::anyhow!("Fail to stat filesystem of {:?}\n{}", path, std::io::Error::last_os_error()));
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> anyhow::Result<u64> {
+    Ok(u64::MAX)
+}
+
+#[cfg(test)]
+fn schedule_fake(jobs: usize, budget: &Budget, free: u64) -> (Vec<usize>, usize, Vec<(usize, String)>) {
+    use std::{sync::atomic::{AtomicUsize, Ordering}, time::Duration};
+
+    let started = Mutex::new(Vec::new());
+    let (current, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let refused = Mutex::new(Vec::new());
+    schedule((0..jobs).collect(), budget, Path::new("/fake"), || Ok(free), |job| {
+        started.lock().unwrap().push(job);
+        most.fetch_max(current.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+        current.fetch_sub(1, Ordering::SeqCst);
+    }, |job, err| refused.lock().unwrap().push((job, err.to_string())));
+    (started.into_inner().unwrap(), most.into_inner(), refused.into_inner().unwrap())
+}
+
+#[test]
+fn test_schedule_order_and_cap() {
+    let budget = |jobs| Budget { jobs, build_jobs: 1, min_free_space: 0, job_disk_estimate: 0 };
+
+    // One at a time, jobs start in the given order.
+    let (started, most, refused) = schedule_fake(5, &budget(1), 0);
+    assert_eq!((started, most, refused.len()), (vec![0, 1, 2, 3, 4], 1, 0));
+
+    // Never more than `jobs` at once, and every job runs once.
+    let (mut started, most, refused) = schedule_fake(8, &budget(3), 0);
+    started.sort();
+    assert_eq!((started, refused.len()), ((0..8).collect(), 0));
+    assert!(most <= 3, "{} jobs at once", most);
+}
+
+#[test]
+fn test_schedule_disk_budget() {
+    let budget = Budget { jobs: 3, build_jobs: 1, min_free_space: 10, job_disk_estimate: 10 };
+
+    // Room for two jobs: the third waits for one of them instead of starting.
+    let (mut started, most, refused) = schedule_fake(6, &budget, 35);
+    started.sort();
+    assert_eq!((started, refused.len()), ((0..6).collect(), 0));
+    assert!(most <= 2, "{} jobs at once", most);
+
+    // Room for none, and nothing running that could free space.
+    let (started, _, refused) = schedule_fake(2, &budget, 15);
+    assert!(started.is_empty());
+    assert_eq!(refused.iter().map(|(job, _)| *job).collect::<Vec<_>>(), [0, 1]);
+    assert!(refused[0].1.contains("15 bytes free"), "{}", refused[0].1);
+
+    // A job reservation alone is enforced too.
+    let budget = Budget { min_free_space: 0, ..budget };
+    let (_, most, refused) = schedule_fake(4, &budget, 10);
+    assert_eq!((most, refused.len()), (1, 0));
+}
+
+#[test]
+fn test_budget_split() {
+    let cpus = thread::available_parallelism().map(|cpus| cpus.get()).unwrap_or(1);
+    let budget = Budget::new(2, None, 0, 0);
+    assert_eq!((budget.jobs, budget.build_jobs), (2, (cpus / 2).max(1)));
+    let budget = Budget::new(0, Some(3), 0, 0);
+    assert_eq!((budget.jobs, budget.build_jobs), (1, 3));
+}
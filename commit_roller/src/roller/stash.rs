@@ -1,11 +1,112 @@
-use std::{path::{PathBuf, Path}, fs::{File, create_dir_all, remove_dir_all}, io::{BufReader, Write}, process::Command};
+use std::{path::{PathBuf, Path}, fs::{File, create_dir_all, remove_dir_all}, io::{BufReader, Write}, sync::{Mutex, MutexGuard}, time::Instant};
 
-use crate::command::command_output::command_output;
+use serde::{Serialize, Deserialize};
 
-use super::gitlog::LogContext;
+use crate::{command::{command_output::CommandError, interrupt}, git::{self, BackendKind}};
 
-pub fn stash_all(repo_dir: &PathBuf, out_dir: &PathBuf, commit_context_json: &PathBuf) {
-    let contexts = match parse_commit_context_json(commit_context_json) {
+use super::{config::{ConfigTemplate, TemplateVars}, gitlog::{short_hash, LogContext}, journal::{Journal, Phase}, recipe::BuildRecipe, scheduler::{self, Budget}, store::InstallStore, summary::{self, ErrorClass, JobSummary, Status}, workspace::{self, WorkspaceStrategy}};
+
+/// `find_commit` writes a report with `contexts` and `failures`; older runs
+/// wrote a bare list of contexts.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ContextFile {
+    Report { contexts: Vec<LogContext> },
+    List(Vec<LogContext>)
+}
+
+pub struct StashOptions {
+    pub backend: BackendKind,
+    pub workspace: WorkspaceStrategy,
+    pub config_template: ConfigTemplate,
+    // Toolchains are installed into `<install_root>/store` and linked from
+    // `<install_root>/<title>/<hash>_<side>`.
+    pub install_root: PathBuf,
+    pub recipe: BuildRecipe,
+    pub budget: Budget,
+    // Lines of a failing step's log shown in its error.
+    pub log_tail: usize,
+    // Remove the workspaces of jobs stopped by SIGINT/SIGTERM.
+    pub clean_interrupted: bool,
+    // Skip the phases an earlier run recorded as done in its journal.
+    pub resume: bool
+}
+
+/// What every job of a run shares.
+pub struct Rollback<'a> {
+    repo_dir: &'a Path,
+    out_dir: &'a Path,
+    options: &'a StashOptions,
+    journal: Journal,
+    store: InstallStore,
+    // `git worktree add`/`remove` of concurrent jobs race on the metadata in
+    // `repo_dir`, so worktrees are made one at a time.
+    workspace_lock: Mutex<()>,
+    // Outcome of every job with its position in the order of the jobs.
+    summaries: Mutex<Vec<(usize, JobSummary)>>
+}
+
+impl<'a> Rollback<'a> {
+    /// Prepares `out_dir` and its journal for building toolchains of
+    /// `repo_dir`.
+    pub fn new(repo_dir: &'a Path, out_dir: &'a Path, options: &'a StashOptions) -> anyhow::Result<Self> {
+        // Metadata of worktrees removed by hand or by an interrupted run.
+        if options.workspace == WorkspaceStrategy::Worktree {
+            if let Err(err) = workspace::prune(repo_dir) {
+                eprintln!("warning: fail to prune worktrees of {:?}\n{}", repo_dir, err);
+            }
+        }
+
+        create_dir_all(out_dir)
+            .map_err(|err| anyhow::anyhow!("Fail to create out dir {:?}\n{}", out_dir, err))?;
+
+        let journal = Journal::open(out_dir, options.resume)
+            .map_err(|err| anyhow::anyhow!("Fail to open journal in {:?}\n{}", out_dir, err))?;
+        println!("recording progress in {:?}", journal.path());
+
+        Ok(Rollback {
+            repo_dir,
+            out_dir,
+            options,
+            journal,
+            store: InstallStore::new(&options.install_root),
+            workspace_lock: Mutex::new(()),
+            summaries: Mutex::new(Vec::new())
+        })
+    }
+
+    /// Checks out, builds and installs one side of a context, see `stash`.
+    pub fn stash(&self, context: &LogContext, side: Side) -> JobSummary {
+        stash(self, context, side)
+    }
+}
+
+/// Which commit of a context a checkout is at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Side {
+    Cur,
+    Old
+}
+
+impl Side {
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Cur => "cur",
+            Side::Old => "old",
+        }
+    }
+
+    pub fn hash(self, context: &LogContext) -> &str {
+        match self {
+            Side::Cur => &context.hash_cur,
+            Side::Old => &context.hash_old,
+        }
+    }
+}
+
+pub fn stash_all(repo_dir: &Path, out_dir: &Path, commit_context_json: &Path, options: &StashOptions) {
+    let mut contexts = match parse_commit_context_json(commit_context_json) {
         Ok(contexts) => contexts,
         Err(err) => {
             eprintln!("Fail to parse commit_context_json file {:?}\n{}", commit_context_json, err);
@@ -13,156 +114,294 @@ pub fn stash_all(repo_dir: &PathBuf, out_dir: &PathBuf, commit_context_json: &Pa
         }
     };
 
-    contexts.iter().for_each(|context| {
-        let (repo_new, repo_old, repo_root) = match copy_repo(repo_dir, out_dir, context){
-            Ok((repo_new, repo_old, repo_root)) => (repo_new, repo_old, repo_root),
-            Err(err) => {
-                eprintln!("Fail to make copy of repo to {:?}\n{}", context, err);
-                return;
-            }
-        };
-
-        // println!("{:?}\n{:?}", repo_new, repo_old);
+    println!("roll back {} contexts of {:?} into {:?} with {:?} workspaces", contexts.len(), repo_dir, out_dir, options.workspace);
+    println!("running {} jobs at a time with {} build jobs each", options.budget.jobs, options.budget.build_jobs);
 
-        match checkout(&repo_new, &context.hash_cur) {
-            Ok(_) => 
-                eprintln!("succesfully stash {:?} to commit {:?}",  context.title, context.hash_cur),
-            Err(err) => 
-                eprintln!("Fail to stash {:?} to commit {:?}\n{}", context.title, context.hash_cur, err),
+    let run = match Rollback::new(repo_dir, out_dir, options) {
+        Ok(run) => run,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
         }
+    };
 
-        match build(&repo_new) {
-            Ok(()) => 
-                println!("succesfully build and install {:?} {:?}", &context.title, &context.hash_cur),
-            Err(err) =>
-                eprintln!("Fail to build {:?} {:?}\n{}", &context.title, &context.hash_cur, err),
+    // Higher priorities first, otherwise in the order of the file.
+    contexts.sort_by_key(|context| std::cmp::Reverse(context.priority.unwrap_or(0)));
+    let jobs = contexts.iter()
+        .flat_map(|context| [(context, Side::Cur), (context, Side::Old)])
+        .enumerate()
+        .collect();
+
+    scheduler::run(
+        jobs,
+        &options.budget,
+        out_dir,
+        |(index, (context, side))| {
+            let summary = stash(&run, context, side);
+            run.summaries.lock().unwrap().push((index, summary));
+        },
+        |(index, (context, side)), err| {
+            eprintln!("Fail to start {:?} {:?}\n{}", context.title, short_hash(side.hash(context)), err);
+            let mut summary = new_summary(&run, context, side);
+            summary.fail(ErrorClass::DiskSpace, err);
+            run.summaries.lock().unwrap().push((index, summary));
+        },
+    );
+
+    let mut summaries = run.summaries.into_inner().unwrap();
+    summaries.sort_by_key(|(index, _)| *index);
+    let summaries: Vec<_> = summaries.into_iter().map(|(_, summary)| summary).collect();
+
+    println!();
+    summary::print_table(&summaries);
+    match summary::write(out_dir, &summaries) {
+        Ok(path) => println!("succesfully write summary to {:?}", path),
+        Err(err) => eprintln!("Fail to write summary to {:?}\n{}", out_dir, err),
+    }
+
+    if interrupt::is_interrupted() {
+        eprintln!("interrupted, rerun with --resume to continue from {:?}", run.journal.path());
+    }
+}
+
+/// Checks out, builds and installs one side of a context in a workspace of
+/// its own, which is removed afterwards. Phases the journal records as done
+/// are skipped, and so is everything when the install store already has the
+/// toolchain.
+fn stash(run: &Rollback, context: &LogContext, side: Side) -> JobSummary {
+    let start = Instant::now();
+    let mut summary = new_summary(run, context, side);
+    stash_phases(run, context, side, &mut summary);
+    summary.set_duration(start.elapsed());
+    summary
+}
+
+fn stash_phases(run: &Rollback, context: &LogContext, side: Side, summary: &mut JobSummary) {
+    let (options, journal) = (run.options, &run.journal);
+    let hash = side.hash(context);
+    let short = short_hash(hash);
+
+    if journal.is_done(context, side, Phase::Cleaned) {
+        println!("skip {:?} {:?}, already done", context.title, short);
+        (summary.checkout, summary.build, summary.install) = (Status::Done, Status::Done, Status::Done);
+        return;
+    }
+    // Jobs not started yet are left to a resumed run.
+    if interrupt::is_interrupted() {
+        return;
+    }
+
+    let title = context.title.replace(' ', "_");
+    let link = install_prefix(&options.install_root, context, side);
+    let mut vars = TemplateVars { title: &title, hash, side: side.name(), prefix: &link };
+    let key = match run.store.key(&options.config_template, &options.recipe, &vars) {
+        Ok(key) => key,
+        Err(err) => {
+            eprintln!("Fail to render config of {:?} {:?}\n{}", context.title, short, err);
+            summary.fail(ErrorClass::Other, err);
+            return;
         }
+    };
+    let prefix = run.store.prefix(&key);
+    vars.prefix = &prefix;
 
-        match checkout(&repo_old, &context.hash_old) {
-            Ok(_) => 
-                eprintln!("succesfully stash {:?} to commit {:?}",  context.title, context.hash_old),
-            Err(err) => 
-                eprintln!("Fail to stash {:?} to commit {:?}\n{}", context.title, context.hash_old, err),
+    // Held until the toolchain is installed, so a job needing the same one
+    // waits for it instead of building it too.
+    let building = run.store.lock(&key);
+    let _building = building.lock().unwrap();
+
+    if run.store.is_installed(&key) {
+        let result = run.store.link(&key, &link);
+        journal.record(context, side, Phase::Installed, &result);
+        summary.record(Phase::Installed, &result);
+        if result.is_ok() {
+            (summary.checkout, summary.build, summary.install) = (Status::Done, Status::Done, Status::Reused);
         }
-    
-        match build(&repo_old) {
-            Ok(()) => 
-                println!("succesfully build and install {:?} {:?}", &context.title, &context.hash_old),
+        match result {
+            Ok(()) =>
+                println!("succesfully reuse installed {:?} {:?}: {:?} -> {:?}", context.title, short, link, prefix),
             Err(err) =>
-                eprintln!("Fail to build {:?} {:?}\n{}", &context.title, &context.hash_old, err),
+                eprintln!("Fail to link installed {:?} {:?}\n{}", context.title, short, err),
         }
-
-        match remove_dir_all(repo_root.as_path()){
-            Ok(_) => (),
-            Err(err) => 
-                eprintln!("Fail to remove tmp dir {:?}\n{}", repo_root, err),
+        // Left behind by an earlier run that installed the toolchain but
+        // stopped before removing its workspace.
+        if journal.is_done(context, side, Phase::Copied) {
+            clean(run, context, side, summary);
         }
-    });
-}
-
-fn parse_commit_context_json(commit_context_json: &PathBuf) -> anyhow::Result<Vec<LogContext>> {
-    let fptr = File::open(commit_context_json)?;
-    let reader = BufReader::new(fptr);
+        return;
+    }
 
-    let contexts = serde_json::from_reader(reader)?;
-    Ok(contexts)
-}
+    let (repo, logs) = job_paths(run.out_dir, context, side);
+    let done = |phase| if journal.is_done(context, side, phase) { Status::Done } else { Status::NotRun };
+    (summary.checkout, summary.build, summary.install) = (done(Phase::CheckedOut), done(Phase::Built), done(Phase::Installed));
 
-fn copy_repo(repo_dir: &PathBuf, out_dir: &PathBuf, context: &LogContext) -> anyhow::Result<(PathBuf, PathBuf, PathBuf)> {
-    let out_dir = out_dir.join(&context.title.replace(' ', "_"));
+    // A workspace gone missing since it was recorded has to be made again,
+    // and so does everything that happened in it.
+    if journal.is_done(context, side, Phase::Copied) && !repo.is_dir() {
+        journal.reset(context, side);
+    }
 
-    create_dir_all(&out_dir)?;
+    if !journal.is_done(context, side, Phase::Copied) {
+        let _guard = lock_workspaces(run);
+        let result = copy_repo(run.repo_dir, &repo, &vars, options);
+        journal.record(context, side, Phase::Copied, &result);
+        summary.record(Phase::Copied, &result);
+        if let Err(err) = result {
+            eprintln!("Fail to make copy of repo to {:?}\n{}", context, err);
+            abandon_if_interrupted(run, &repo);
+            return;
+        }
+    }
 
-    let new_repo = out_dir.join(context.hash_cur.clone() + "_cur");
-    let old_repo = out_dir.join(context.hash_old.clone() + "_old");
+    if !journal.is_done(context, side, Phase::CheckedOut) {
+        let result = checkout(options.backend, &repo, hash);
+        journal.record(context, side, Phase::CheckedOut, &result);
+        summary.record(Phase::CheckedOut, &result);
+        match result {
+            Ok(_) => 
+                eprintln!("succesfully stash {:?} to commit {:?}",  context.title, short),
+            Err(err) => {
+                eprintln!("Fail to stash {:?} to commit {:?}\n{}", context.title, short, err);
+                abandon_if_interrupted(run, &repo);
+                return;
+            }
+        }
+    }
 
-    if new_repo.is_dir() {
-        eprintln!("warning: {:?} already exists.", &new_repo);
-    } else {
-        copy(&repo_dir, &new_repo)?;
-            // .with_context(||{format!("Fail to copy repo from {:?} to {:?}", repo_dir, new_repo)})?;
-        write_config_and_create_target_dir(&new_repo, &PathBuf::from(&context.title.replace(' ', "_")).join(context.hash_cur.clone() + "_cur"))?;
-        println!("succesfully create copy of repo: {:?} -> {:?}", repo_dir, new_repo);
+    let jobs = options.budget.build_jobs;
+    if !journal.is_done(context, side, Phase::Built) {
+        let result = options.recipe.run(options.recipe.build_steps(), &repo, &prefix, jobs, &logs, options.log_tail);
+        journal.record(context, side, Phase::Built, &result);
+        summary.record(Phase::Built, &result);
+        if let Err(err) = result {
+            eprintln!("Fail to build {:?} {:?}{}\n{:#}", &context.title, short, timed_out(&err), err);
+            abandon_if_interrupted(run, &repo);
+            return;
+        }
     }
 
-    if old_repo.is_dir() {
-        eprintln!("warning: {:?} already exists.", &old_repo);
-    } else {
-        copy(&repo_dir, &old_repo)?;
-            // .with_context(||{format!("Fail to copy repo from {:?} to {:?}", repo_dir, new_repo)})?;
-            write_config_and_create_target_dir(&old_repo, &PathBuf::from(&context.title.replace(' ', "_")).join(context.hash_old.clone() + "_old"))?;
-        println!("succesfully create copy of repo: {:?} -> {:?}", repo_dir, old_repo);
+    if !journal.is_done(context, side, Phase::Installed) {
+        let result = options.recipe.run(options.recipe.install_steps(), &repo, &prefix, jobs, &logs, options.log_tail)
+            .and_then(|()| {
+                let config = options.recipe.write_config.then(|| options.config_template.render(&vars)).transpose()?;
+                run.store.commit(&key, hash, config, &options.recipe)
+            })
+            .and_then(|()| run.store.link(&key, &link));
+        journal.record(context, side, Phase::Installed, &result);
+        summary.record(Phase::Installed, &result);
+        if let Err(err) = result {
+            eprintln!("Fail to install {:?} {:?}{}\n{:#}", &context.title, short, timed_out(&err), err);
+            abandon_if_interrupted(run, &repo);
+            return;
+        }
     }
+    println!("succesfully build and install {:?} {:?}: {:?} -> {:?}, logs in {:?}", &context.title, short, link, prefix, logs);
 
-    Ok((new_repo, old_repo, out_dir))
+    clean(run, context, side, summary);
 }
 
-#[cfg(unix)]
-fn copy(from: &Path, to: &Path) -> anyhow::Result<()> {
-    let mut cmd = Command::new("cp");
-    cmd.arg("-pLR").arg(from).arg(to);
-    command_output(&mut cmd)?;
-    Ok(())
+/// Removes the workspace of a job whose toolchain is installed.
+fn clean(run: &Rollback, context: &LogContext, side: Side, summary: &mut JobSummary) {
+    let (repo, _) = job_paths(run.out_dir, context, side);
+    let _guard = lock_workspaces(run);
+    let result = workspace::remove(run.options.workspace, run.repo_dir, &repo);
+    run.journal.record(context, side, Phase::Cleaned, &result);
+    summary.record(Phase::Cleaned, &result);
+    if let Err(err) = result {
+        eprintln!("Fail to remove workspace {:?}\n{}", repo, err);
+    }
 }
 
-fn checkout(dir: &PathBuf, commit_id: &String) -> anyhow::Result<()> {
-    let mut cmd = Command::new("git");
-    cmd.current_dir(dir)
-        .arg("checkout")
-        .arg(commit_id);
+/// Removes the workspace of a job stopped by SIGINT/SIGTERM if asked to, so
+/// a resumed run makes it again. `git` refuses to run once interrupted, so
+/// the metadata of a worktree is left to the `prune` of the next run.
+fn abandon_if_interrupted(run: &Rollback, repo: &Path) {
+    if !(interrupt::is_interrupted() && run.options.clean_interrupted && repo.exists()) {
+        return;
+    }
+    match remove_dir_all(repo) {
+        Ok(()) => eprintln!("removed interrupted workspace {:?}", repo),
+        Err(err) => eprintln!("Fail to remove interrupted workspace {:?}\n{}", repo, err),
+    }
+}
 
-    let _ = command_output(&mut cmd)?;
+fn new_summary(run: &Rollback, context: &LogContext, side: Side) -> JobSummary {
+    let (_, logs) = job_paths(run.out_dir, context, side);
+    JobSummary::new(context, side, &install_prefix(&run.options.install_root, context, side), &logs)
+}
 
-    // cmd = Command::new("git");
-    // cmd.current_dir(dir)
-    //     .arg("add")
-    //     .arg(".");
-    
-    // let _ = command_output(&mut cmd)?;
+/// `, timed out after ...` for errors of commands killed by their timeout.
+fn timed_out(err: &anyhow::Error) -> String {
+    match CommandError::find(err) {
+        Some(CommandError::TimedOut { timeout, .. }) => format!(", timed out after {:?}", timeout),
+        _ => String::new(),
+    }
+}
 
-    // cmd = Command::new("git");
-    // cmd.current_dir(dir)
-    //     .arg("commit")
-    //     .arg("-m")
-    //     .arg(commit_id);
-    
-    let _ = command_output(&mut cmd)?;
+fn lock_workspaces<'a>(run: &'a Rollback) -> Option<MutexGuard<'a, ()>> {
+    (run.options.workspace == WorkspaceStrategy::Worktree).then(|| run.workspace_lock.lock().unwrap())
+}
 
-    // println!("{:?}", output);
+pub fn parse_commit_context_json(commit_context_json: &Path) -> anyhow::Result<Vec<LogContext>> {
+    let fptr = File::open(commit_context_json)?;
+    let reader = BufReader::new(fptr);
 
-    Ok(())
+    let contexts = match serde_json::from_reader(reader)? {
+        ContextFile::Report { contexts } => contexts,
+        ContextFile::List(contexts) => contexts,
+    };
+    Ok(contexts)
 }
 
-fn write_config_and_create_target_dir(repo_dir: &PathBuf, target_dir: &PathBuf) -> anyhow::Result<()> {
-    let mut fptr = File::create(repo_dir.join("config.toml"))?;
-    
-    let content = "[build]\nbuild = \"x86_64-unknown-linux-gnu\"\n# build-dir = \"/media/workstation/device/home/fxl/rustc/baseline\"\n# cargo = \"/home/workstation/.rustup/toolchains/1.43-x86_64-unknown-linux-gnu/bin/cargo\"\n# rustc = \"/home/workstation/.rustup/toolchains/1.43-x86_64-unknown-linux-gnu/bin/rustc\"\ntarget = [\"x86_64-unknown-linux-gnu\"]\n[install]\n";
-    let prefix = String::from("/media/workstation/device/home/fxl/rustc/targets/") + target_dir.to_str().unwrap();
-    let sysconfdir = "sysconfdir = \"./etc\"\n";
+/// Where the workspace of one side of a context goes, and the logs of its
+/// build, which are kept after the workspace is removed:
+/// `<out_dir>/<title>/<hash>_<side>/{src,logs}`.
+fn job_paths(out_dir: &Path, context: &LogContext, side: Side) -> (PathBuf, PathBuf) {
+    let job_dir = out_dir
+        .join(context.title.replace(' ', "_"))
+        .join(short_hash(side.hash(context)).to_string() + "_" + side.name());
+    (job_dir.join("src"), job_dir.join("logs"))
+}
+
+fn copy_repo(repo_dir: &Path, repo: &Path, vars: &TemplateVars, options: &StashOptions) -> anyhow::Result<()> {
+    // Left behind by a copy that never finished.
+    if repo.exists() {
+        eprintln!("warning: {:?} already exists, making it again.", repo);
+        workspace::remove(options.workspace, repo_dir, repo)?;
+    }
 
-    fptr.write(content.as_bytes())?;
-    fptr.write("prefix = \"".as_bytes())?;
-    fptr.write(prefix.as_bytes())?;
-    fptr.write("\"\n".as_bytes())?;
-    fptr.write(sysconfdir.as_bytes())?;
+    if let Some(parent) = repo.parent() {
+        create_dir_all(parent)?;
+    }
 
-    create_dir_all(prefix)?;
+    workspace::create(options.workspace, repo_dir, repo, vars.hash)?;
+    write_config_and_create_target_dir(repo, options, vars)?;
+    println!("succesfully create copy of repo: {:?} -> {:?}", repo_dir, repo);
 
     Ok(())
 }
 
-fn build(dir: &PathBuf) -> anyhow::Result<()> {
-    let mut cmd = Command::new("./x.py");
-    cmd.current_dir(dir)
-        .arg("build");
-    
-    let _ = command_output(&mut cmd)?;
-    
-    let mut cmd = Command::new("./x.py");
-    cmd.current_dir(dir)
-        .arg("install");
-    
-    let _ = command_output(&mut cmd)?;
+fn checkout(backend: BackendKind, dir: &Path, commit_id: &str) -> anyhow::Result<()> {
+    git::open(backend, dir)?.checkout(commit_id)
+}
+
+/// Where the toolchain of one side of a context is linked from:
+/// `<install_root>/<title>/<hash>_<side>`.
+pub fn install_prefix(install_root: &Path, context: &LogContext, side: Side) -> PathBuf {
+    install_root
+        .join(context.title.replace(' ', "_"))
+        .join(short_hash(side.hash(context)).to_string() + "_" + side.name())
+}
+
+fn write_config_and_create_target_dir(repo_dir: &Path, options: &StashOptions, vars: &TemplateVars) -> anyhow::Result<()> {
+    if options.recipe.write_config {
+        let content = options.config_template.render(vars)?;
+
+        let mut fptr = File::create(repo_dir.join("config.toml"))?;
+        fptr.write_all(content.as_bytes())?;
+    }
+
+    create_dir_all(vars.prefix)?;
 
     Ok(())
 }
@@ -170,8 +409,19 @@ fn build(dir: &PathBuf) -> anyhow::Result<()> {
 #[test]
 fn test_stash() {
     stash_all(
-        &PathBuf::from("/media/workstation/device/home/fxl/rustc/rust"),
-        &PathBuf::from("/media/workstation/disk/fxl/rust"),
-        &PathBuf::from("/media/workstation/device/home/fxl/CommitRoller/commit_roller/out/commit_context.json")
+        Path::new("/media/workstation/device/home/fxl/rustc/rust"),
+        Path::new("/media/workstation/disk/fxl/rust"),
+        Path::new("/media/workstation/device/home/fxl/CommitRoller/commit_roller/out/commit_context.json"),
+        &StashOptions {
+            backend: BackendKind::Cli,
+            workspace: WorkspaceStrategy::Worktree,
+            config_template: ConfigTemplate::load(None).unwrap(),
+            install_root: PathBuf::from("/media/workstation/device/home/fxl/rustc/targets"),
+            recipe: BuildRecipe::load("rustc").unwrap(),
+            budget: Budget::new(1, None, 0, 0),
+            log_tail: 20,
+            clean_interrupted: false,
+            resume: false
+        }
     )
 }
\ No newline at end of file
@@ -0,0 +1,150 @@
+use std::{collections::HashMap, path::{Path, PathBuf}, fs, sync::{Arc, Mutex}};
+
+use chrono::Local;
+use serde::Serialize;
+
+use crate::report;
+
+use super::{config::{ConfigTemplate, TemplateVars}, recipe::BuildRecipe};
+
+/// Installed toolchains under `<install_root>/store/<hash>-<digest>`, where
+/// `digest` covers the rendered config and the build recipe. Whatever built
+/// the same commit the same way is reused instead of built again.
+pub struct InstallStore {
+    root: PathBuf,
+    // One lock per key, so two jobs needing the same toolchain build it once.
+    building: Mutex<HashMap<String, Arc<Mutex<()>>>>
+}
+
+/// Written next to a toolchain once it is installed.
+#[derive(Serialize)]
+struct Manifest<'a> {
+    hash: &'a str,
+    time: String,
+    config: Option<String>,
+    recipe: &'a BuildRecipe
+}
+
+impl InstallStore {
+    pub fn new(install_root: &Path) -> Self {
+        InstallStore { root: install_root.join("store"), building: Mutex::new(HashMap::new()) }
+    }
+
+    /// Key of the toolchain of commit `vars.hash`. `vars.prefix` is ignored
+    /// since the prefix is derived from the key, and so are the interpreter
+    /// and the timeouts of the recipe, which don't change what is built.
+    pub fn key(&self, template: &ConfigTemplate, recipe: &BuildRecipe, vars: &TemplateVars) -> anyhow::Result<String> {
+        let mut recipe = recipe.clone();
+        recipe.python = None;
+        recipe.timeout = None;
+        for step in &mut recipe.steps {
+            step.timeout = None;
+        }
+
+        let mut digest = Fnv::new();
+        if recipe.write_config {
+            // Parsed so that comments and formatting don't count.
+            let config = template.render(&TemplateVars { prefix: &self.root, ..*vars })?.parse::<toml::Value>()?;
+            digest.write(config.to_string().as_bytes());
+        }
+        digest.write(&[0]);
+        digest.write(serde_json::to_string(&recipe)?.as_bytes());
+        Ok(format!("{}-{:016x}", vars.hash, digest.finish()))
+    }
+
+    pub fn prefix(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    /// Lock to hold while building or linking the toolchain of `key`.
+    pub fn lock(&self, key: &str) -> Arc<Mutex<()>> {
+        self.building.lock().unwrap().entry(String::from(key)).or_default().clone()
+    }
+
+    pub fn is_installed(&self, key: &str) -> bool {
+        self.manifest_path(key).is_file() && self.prefix(key).is_dir()
+    }
+
+    /// Marks the toolchain of `key` as completely installed.
+    pub fn commit(&self, key: &str, hash: &str, config: Option<String>, recipe: &BuildRecipe) -> anyhow::Result<()> {
+        let manifest = Manifest { hash, time: Local::now().to_rfc3339(), config, recipe };
+        report::write_json(&self.manifest_path(key), &manifest)
+    }
+
+    /// Points `link` at the toolchain of `key`, replacing an older link.
+    pub fn link(&self, key: &str, link: &Path) -> anyhow::Result<()> {
+        if let Ok(metadata) = fs::symlink_metadata(link) {
+            if metadata.file_type().is_symlink() {
+                fs::remove_file(link)?;
+            } else if metadata.is_dir() && fs::read_dir(link)?.next().is_none() {
+                fs::remove_dir(link)?;
+            } else {
+                return Err(anyhow::anyhow!("{:?} already exists and is not a link into the install store", link));
+            }
+        }
+        if let Some(parent) = link.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        symlink(&self.prefix(key), link)?;
+        Ok(())
+    }
+
+    fn manifest_path(&self, key: &str) -> PathBuf {
+        self.root.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` stays the same across Rust
+/// releases and so can name directories that outlive a run.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[test]
+fn test_store_key() {
+    let store = InstallStore::new(Path::new("/targets"));
+    let template = ConfigTemplate::load(None).unwrap();
+    let rustc = BuildRecipe::load("rustc").unwrap();
+    let vars = |side, prefix| TemplateVars {
+        title: "Treat_macros_as_HIR_items",
+        hash: "7891aae000000000000000000000000000000000",
+        side,
+        prefix: Path::new(prefix)
+    };
+
+    let key = store.key(&template, &rustc, &vars("cur", "/targets/a/7891aae_cur")).unwrap();
+    assert!(key.starts_with("7891aae"));
+    // The default template depends on neither the side nor the prefix.
+    assert_eq!(key, store.key(&template, &rustc, &vars("old", "/targets/b/7891aae_old")).unwrap());
+    assert_ne!(key, store.key(&template, &BuildRecipe::load("make").unwrap(), &vars("cur", "/targets")).unwrap());
+
+    let mut slow = rustc.clone();
+    slow.python = Some(PathBuf::from("/usr/bin/python3"));
+    slow.timeout = Some(7200);
+    slow.steps[0].timeout = Some(3600);
+    assert_eq!(key, store.key(&template, &slow, &vars("cur", "/targets")).unwrap());
+}
@@ -0,0 +1,171 @@
+use std::{fmt, path::{Path, PathBuf}, time::Duration};
+
+use serde::Serialize;
+
+use crate::{command::{command_output::CommandError, interrupt}, report};
+
+use super::{gitlog::{short_hash, LogContext}, journal::Phase, stash::Side};
+
+/// How far one phase of a job got.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    NotRun,
+    Ok,
+    Failed,
+    Interrupted,
+    // Done by an earlier run, according to the journal.
+    Done,
+    // Taken from the install store instead of built.
+    Reused
+}
+
+/// What kind of failure stopped a job.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorClass {
+    Spawn,
+    TimedOut,
+    Interrupted,
+    ExitStatus,
+    DiskSpace,
+    Other
+}
+
+impl ErrorClass {
+    pub fn of(err: &anyhow::Error) -> Self {
+        match CommandError::find(err) {
+            Some(CommandError::Spawn { .. }) => ErrorClass::Spawn,
+            Some(CommandError::TimedOut { .. }) => ErrorClass::TimedOut,
+            Some(CommandError::Interrupted { .. }) => ErrorClass::Interrupted,
+            Some(CommandError::Failed { .. }) => ErrorClass::ExitStatus,
+            None if interrupt::is_interrupted() => ErrorClass::Interrupted,
+            None => ErrorClass::Other,
+        }
+    }
+}
+
+/// Outcome of rolling back one side of a context, as written to
+/// `summary.json`.
+#[derive(Serialize, Debug)]
+pub struct JobSummary {
+    pub title: String,
+    pub side: &'static str,
+    pub hash: String,
+    pub checkout: Status,
+    pub build: Status,
+    pub install: Status,
+    pub duration_secs: f64,
+    // Where the toolchain is linked from.
+    pub prefix: PathBuf,
+    pub logs: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorClass>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>
+}
+
+impl JobSummary {
+    pub fn new(context: &LogContext, side: Side, prefix: &Path, logs: &Path) -> Self {
+        JobSummary {
+            title: context.title.clone(),
+            side: side.name(),
+            hash: String::from(side.hash(context)),
+            checkout: Status::NotRun,
+            build: Status::NotRun,
+            install: Status::NotRun,
+            duration_secs: 0.0,
+            prefix: prefix.to_path_buf(),
+            logs: logs.to_path_buf(),
+            error: None,
+            message: None
+        }
+    }
+
+    /// Sets the status of `phase` from its result.
+    pub fn record<T>(&mut self, phase: Phase, result: &anyhow::Result<T>) {
+        let status = match result {
+            Ok(_) => Status::Ok,
+            Err(err) => {
+                self.fail(ErrorClass::of(err), err);
+                if self.error == Some(ErrorClass::Interrupted) { Status::Interrupted } else { Status::Failed }
+            }
+        };
+        match phase {
+            // Checked out only once `CheckedOut` is recorded.
+            Phase::Copied if status == Status::Ok => (),
+            Phase::Copied | Phase::CheckedOut => self.checkout = status,
+            Phase::Built => self.build = status,
+            Phase::Installed => self.install = status,
+            // The toolchain is fine even if its workspace is left behind.
+            Phase::Cleaned => (),
+        }
+    }
+
+    pub fn fail(&mut self, class: ErrorClass, err: impl fmt::Display) {
+        self.error = Some(class);
+        self.message = Some(format!("{:#}", err));
+    }
+
+    /// Whether the toolchain is in place at `prefix`.
+    pub fn is_installed(&self) -> bool {
+        matches!(self.install, Status::Ok | Status::Done | Status::Reused)
+    }
+
+    pub fn set_duration(&mut self, duration: Duration) {
+        self.duration_secs = duration.as_secs_f64();
+    }
+}
+
+/// Writes `summary.json` into `out_dir`.
+pub fn write(out_dir: &Path, jobs: &[JobSummary]) -> anyhow::Result<PathBuf> {
+    let path = out_dir.join("summary.json");
+    report::write_json(&path, &jobs)?;
+    Ok(path)
+}
+
+/// Prints one row per job, aligned for pasting into notes.
+pub fn print_table(jobs: &[JobSummary]) {
+    let header = ["title", "side", "hash", "checkout", "build", "install", "duration", "error", "prefix", "logs"];
+    let rows: Vec<[String; 10]> = jobs.iter()
+        .map(|job| [
+            job.title.clone(),
+            String::from(job.side),
+            String::from(short_hash(&job.hash)),
+            serde_name(&job.checkout),
+            serde_name(&job.build),
+            serde_name(&job.install),
+            format_duration(job.duration_secs),
+            job.error.map(|class| serde_name(&class)).unwrap_or_default(),
+            job.prefix.display().to_string(),
+            job.logs.display().to_string(),
+        ])
+        .collect();
+
+    report::print_table(header, &rows);
+}
+
+fn serde_name(value: &impl Serialize) -> String {
+    serde_json::to_value(value).ok()
+        .and_then(|value| value.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[test]
+fn test_record_phases() {
+    let context = LogContext::new("t", "7891aae", "6965b7a");
+    let mut job = JobSummary::new(&context, Side::Cur, Path::new("/prefix"), Path::new("/logs"));
+    job.record(Phase::Copied, &Ok(()));
+    assert_eq!(job.checkout, Status::NotRun);
+    job.record(Phase::CheckedOut, &Ok(()));
+    job.record(Phase::Built, &Err::<(), _>(anyhow::anyhow!("step build failed")));
+    assert_eq!((job.checkout, job.build, job.install), (Status::Ok, Status::Failed, Status::NotRun));
+    assert_eq!(job.error, Some(ErrorClass::Other));
+    assert_eq!(format_duration(3725.4), "1h02m05s");
+}
@@ -0,0 +1,94 @@
+use std::{path::Path, fs::remove_dir_all, process::Command};
+
+use crate::{command::command_output::{command_output, command_output_with_timeout}, git::{self, GIT_TIMEOUT}};
+
+/// How the `_cur`/`_old` checkouts of a context are made from `repo_dir`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum WorkspaceStrategy {
+    // `git worktree add --detach`, sharing `.git` of `repo_dir`.
+    Worktree,
+    // `git clone --shared`, an independent `.git` borrowing the objects of
+    // `repo_dir` through alternates. `repo_dir` must not be gc'ed meanwhile.
+    SharedClone,
+    // `git clone --reference --dissociate`, borrows the objects of `repo_dir`
+    // only while cloning and then copies them into the clone, which thus
+    // survives `repo_dir` being gc'ed at the cost of a full object store.
+    ReferenceClone,
+    // A full `cp -pLR` of `repo_dir`, including `.git` and `build/`.
+    Copy
+}
+
+/// Creates a workspace of `repo_dir` at `dir`, checked out at `hash` where
+/// the strategy supports it.
+pub fn create(strategy: WorkspaceStrategy, repo_dir: &Path, dir: &Path, hash: &str) -> anyhow::Result<()> {
+    match strategy {
+        WorkspaceStrategy::Worktree => {
+            let mut cmd = git::command();
+            cmd.current_dir(repo_dir)
+                .arg("worktree")
+                .arg("add")
+                .arg("--detach")
+                .arg(dir)
+                .arg(hash);
+            command_output_with_timeout(&mut cmd, Some(GIT_TIMEOUT))?;
+        }
+        WorkspaceStrategy::SharedClone | WorkspaceStrategy::ReferenceClone => {
+            // Left for `checkout` to populate at `hash`.
+            let mut cmd = git::command();
+            cmd.arg("clone").arg("--no-checkout");
+            if strategy == WorkspaceStrategy::SharedClone {
+                cmd.arg("--shared");
+            } else {
+                cmd.arg("--reference").arg(repo_dir).arg("--dissociate");
+            }
+            cmd.arg(repo_dir).arg(dir);
+            command_output_with_timeout(&mut cmd, Some(GIT_TIMEOUT))?;
+        }
+        WorkspaceStrategy::Copy => copy(repo_dir, dir)?,
+    }
+    Ok(())
+}
+
+/// Removes a workspace created by `create`, including the metadata a
+/// worktree leaves in `repo_dir/.git/worktrees`.
+pub fn remove(strategy: WorkspaceStrategy, repo_dir: &Path, dir: &Path) -> anyhow::Result<()> {
+    if strategy == WorkspaceStrategy::Worktree {
+        // `--force` as the build leaves `config.toml` and `build/` behind.
+        let mut cmd = git::command();
+        cmd.current_dir(repo_dir)
+            .arg("worktree")
+            .arg("remove")
+            .arg("--force")
+            .arg(dir);
+        if let Err(err) = command_output_with_timeout(&mut cmd, Some(GIT_TIMEOUT)) {
+            eprintln!("warning: fail to remove worktree {:?}, removing it by hand\n{}", dir, err);
+        }
+    }
+
+    if dir.exists() {
+        remove_dir_all(dir)?;
+    }
+
+    if strategy == WorkspaceStrategy::Worktree {
+        prune(repo_dir)?;
+    }
+    Ok(())
+}
+
+/// Drops metadata of worktrees whose directory is gone.
+pub fn prune(repo_dir: &Path) -> anyhow::Result<()> {
+    let mut cmd = git::command();
+    cmd.current_dir(repo_dir)
+        .arg("worktree")
+        .arg("prune");
+    command_output_with_timeout(&mut cmd, Some(GIT_TIMEOUT))?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy(from: &Path, to: &Path) -> anyhow::Result<()> {
+    let mut cmd = Command::new("cp");
+    cmd.arg("-pLR").arg(from).arg(to);
+    command_output(&mut cmd)?;
+    Ok(())
+}
@@ -1 +1 @@
-./commit_roller/target/release/commit_roller roll_back --repo-dir /media/workstation/device/home/fxl/rustc/baseline --commit_id_json /media/workstation/device/home/fxl/CommitRoller/commit_roller/out/commit_context_new.json --out-dir /media/workstation/device/home/fxl/rustc/tmp
\ No newline at end of file
+./commit_roller/target/release/commit_roller roll_back --repo-dir /media/workstation/device/home/fxl/rustc/baseline --commit_id_json /media/workstation/device/home/fxl/CommitRoller/commit_roller/out/commit_context_new.json --out-dir /media/workstation/device/home/fxl/rustc/tmp --install-root /media/workstation/device/home/fxl/rustc/targets --config-template commit_roller/config.template.toml
\ No newline at end of file